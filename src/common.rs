@@ -1,14 +1,16 @@
 #![allow(dead_code)]
 
-use globset::GlobSet;
-use std::env::{current_dir, set_current_dir};
+use globset::{Glob, GlobMatcher, GlobSet, GlobSetBuilder};
+use std::collections::HashSet;
+use std::env::{current_dir, set_current_dir, var_os};
 use std::path::{Path, PathBuf};
 
 use git2::{
-    Branch, BranchType, Commit, Config, Cred, CredentialType, Error, ErrorCode, FetchOptions,
-    MergeOptions, PushOptions, RemoteCallbacks, Sort, StatusOptions,
+    Branch, BranchType, Commit, Config, Cred, CredentialType, Delta, DiffFindOptions, DiffOptions,
+    Direction, Error, ErrorClass, ErrorCode, FetchOptions, Index, IndexEntry, MergeOptions,
+    ObjectType, PushOptions, ReferenceType, RemoteCallbacks, Sort, StashFlags, StatusOptions,
 };
-pub use git2::{Oid, Repository};
+pub use git2::{Oid, Repository, RepositoryState, Time};
 
 pub struct Git {
     pub repo: Repository,
@@ -16,78 +18,257 @@ pub struct Git {
     pub head_hash: String,
     pub branch_name: Option<String>,
     pub upstream: Option<String>,
+    pub is_detached: bool,
     pub config: Config,
+    /// Ignore the whitespace changes when merging: the whitespace-only conflicts are resolved.
+    pub merge_ignore_whitespace: bool,
 }
 
 impl Git {
     pub fn open() -> Result<Git, Error> {
-        if let Some(path) = find_git_repository()? {
-            set_current_dir(path).map_err(|e| Error::from_str(&e.to_string()))?;
+        Git::open_at(".")
+    }
+
+    /// The repository is searched from `path` up to the root, unless `GIT_TOOLS_NO_DISCOVERY` is
+    /// set: then it must be exactly at `path`.
+    ///
+    /// If `GIT_DIR` is set, the repository is opened like git does instead (`path` and
+    /// `GIT_TOOLS_NO_DISCOVERY` are not used): `GIT_DIR` is relative to the current working
+    /// directory and the working tree is `GIT_WORK_TREE` if it is set.
+    pub fn open_at<P: AsRef<Path>>(path: P) -> Result<Git, Error> {
+        let git = Git::discover(path)?;
+        if let Some(workdir) = git.repo.workdir() {
+            set_current_dir(workdir).map_err(|e| Error::from_str(&e.to_string()))?;
+        }
+
+        Ok(git)
+    }
+
+    /// Same as `open_at` but `config` reads the configuration files directly instead of a
+    /// snapshot. (Cheaper for the commands that read only a few keys and never write any.)
+    pub fn open_light<P: AsRef<Path>>(path: P) -> Result<Git, Error> {
+        let git = Git::discover_with(path, false)?;
+        if let Some(workdir) = git.repo.workdir() {
+            set_current_dir(workdir).map_err(|e| Error::from_str(&e.to_string()))?;
+        }
+
+        Ok(git)
+    }
+
+    /// Same as `open_at` but the current working directory is not changed to the root of the
+    /// repository. The paths given to the methods are still relative to the root.
+    pub fn discover<P: AsRef<Path>>(path: P) -> Result<Git, Error> {
+        Git::discover_with(path, true)
+    }
+
+    fn discover_with<P: AsRef<Path>>(path: P, snapshot: bool) -> Result<Git, Error> {
+        // NOTE: GIT_DIR is set by `git --git-dir` and in the hooks, GIT_WORK_TREE by
+        //       `git --work-tree` (`Repository::open_from_env` doesn't support it)
+        if let Some(git_dir) = var_os("GIT_DIR").filter(|x| !x.is_empty()) {
+            let repo = Repository::open(git_dir)?;
+            if let Some(work_tree) = var_os("GIT_WORK_TREE").filter(|x| !x.is_empty()) {
+                repo.set_workdir(Path::new(&work_tree), false)?;
+            }
+
+            return Git::from_repo_with(repo, snapshot);
         }
 
-        let repo = Repository::open(".")?;
-        let head_message;
-        let head_hash;
-        let branch_name;
-        let upstream;
+        let path = current_dir()
+            .map_err(|e| Error::from_str(&e.to_string()))?
+            .join(path);
+
+        // NOTE: with GIT_TOOLS_NO_DISCOVERY the repository must be exactly at the path
+        let path = if var_os("GIT_TOOLS_NO_DISCOVERY").is_some() {
+            path
+        } else {
+            find_git_repository(&path)?.unwrap_or(path)
+        };
+
+        Git::from_repo_with(Repository::open(path)?, snapshot)
+    }
+
+    /// Wrap a repository already opened, without discovery and without changing the current
+    /// working directory.
+    pub fn from_repo(repo: Repository) -> Result<Git, Error> {
+        Git::from_repo_with(repo, true)
+    }
+
+    fn from_repo_with(repo: Repository, snapshot: bool) -> Result<Git, Error> {
+        // NOTE: only a snapshot can be read with `get_str` and doesn't change while running
+        let config = if snapshot {
+            repo.config()?.snapshot()?
+        } else {
+            repo.config()?
+        };
+
+        let mut git = Git {
+            repo,
+            head_message: String::new(),
+            head_hash: String::new(),
+            branch_name: None,
+            upstream: None,
+            is_detached: false,
+            config,
+            merge_ignore_whitespace: false,
+        };
+        git.refresh()?;
+
+        Ok(git)
+    }
 
+    /// Read again HEAD, its branch and its upstream. (Needed only if HEAD has been moved outside
+    /// of this struct, e.g. by a command.)
+    pub fn refresh(&mut self) -> Result<(), Error> {
         {
-            let (object, maybe_ref) = repo.revparse_ext("HEAD")?;
+            let (object, maybe_ref) = self.repo.revparse_ext("HEAD")?;
             let commit = object.as_commit().unwrap();
-            head_message = commit.message().unwrap().to_string();
-            head_hash = format!("{}", object.id());
-            branch_name = maybe_ref.and_then(|x| {
+            self.head_message = commit.message().unwrap().to_string();
+            self.head_hash = format!("{}", object.id());
+            self.branch_name = maybe_ref.and_then(|x| {
                 x.shorthand()
                     .filter(|&x| x != "HEAD")
                     .map(|x| x.to_string())
             });
-            upstream = if let Some(name) = branch_name.as_ref() {
-                if let Ok(remote_branch) = repo
-                    .find_branch(name, BranchType::Local)
-                    .and_then(|x| x.upstream())
-                {
-                    remote_branch.name()?.map(|x| x.to_string())
-                } else {
-                    None
-                }
-            } else {
-                None
-            };
         }
+        self.upstream = match self.branch_name.as_ref() {
+            Some(name) => branch_upstream(&self.repo, name)?,
+            None => None,
+        };
+        self.is_detached = self.repo.head_detached()?;
 
-        let config = repo.config()?.snapshot()?;
+        Ok(())
+    }
 
-        Ok(Git {
-            repo,
-            head_message,
-            head_hash,
-            branch_name,
-            upstream,
-            config,
-        })
+    /// The commit of HEAD is found from `head_hash` without resolving HEAD again.
+    fn head_oid(&self) -> Result<Oid, Error> {
+        Oid::from_str(&self.head_hash)
     }
 
+    pub fn state(&self) -> RepositoryState {
+        self.repo.state()
+    }
+
+    /// Description of the operation in progress (e.g. "rebase in progress"), `None` if there is
+    /// none.
+    pub fn state_description(&self) -> Option<&'static str> {
+        match self.state() {
+            RepositoryState::Clean => None,
+            RepositoryState::Merge => Some("merge in progress"),
+            RepositoryState::Revert | RepositoryState::RevertSequence => Some("revert in progress"),
+            RepositoryState::CherryPick | RepositoryState::CherryPickSequence => {
+                Some("cherry-pick in progress")
+            }
+            RepositoryState::Bisect => Some("bisect in progress"),
+            RepositoryState::Rebase
+            | RepositoryState::RebaseInteractive
+            | RepositoryState::RebaseMerge => Some("rebase in progress"),
+            RepositoryState::ApplyMailbox | RepositoryState::ApplyMailboxOrRebase => {
+                Some("am in progress")
+            }
+        }
+    }
+
+    /// Paths of the files changed in the index or in the working directory. A renamed file is
+    /// listed once, under its new path.
     pub fn get_staged_and_unstaged_files(&self) -> Result<Vec<String>, Error> {
         let mut files = Vec::new();
-        let mut options = StatusOptions::new();
+        for status in self.statuses(50)? {
+            if !files.contains(&status.path) {
+                files.push(status.path);
+            }
+        }
+
+        Ok(files)
+    }
+
+    /// Changes of the index (staged) and of the working directory (unstaged), like `git status`.
+    /// The renames and copies are detected for the files similar by at least `rename_threshold`
+    /// percent (50 like git by default).
+    pub fn statuses(&self, rename_threshold: u16) -> Result<Vec<FileStatus>, Error> {
+        let head_tree = self.repo.find_commit(self.head_oid()?)?.tree()?;
+        let index = self.repo.index()?;
+
+        let mut options = DiffOptions::new();
         options.include_untracked(true);
-        options.include_ignored(false);
+        options.recurse_untracked_dirs(true);
+        let mut find_options = DiffFindOptions::new();
+        find_options.renames(true);
+        find_options.copies(true);
+        // NOTE: a file moved without `git add` is a deleted file and an untracked one
+        find_options.for_untracked(true);
+        find_options.rename_threshold(rename_threshold);
+        find_options.copy_threshold(rename_threshold);
+
+        let staged = self
+            .repo
+            .diff_tree_to_index(Some(&head_tree), Some(&index), None)?;
+        let unstaged = self
+            .repo
+            .diff_index_to_workdir(Some(&index), Some(&mut options))?;
+
+        let mut files = Vec::new();
+        for (is_staged, mut diff) in [(true, staged), (false, unstaged)] {
+            diff.find_similar(Some(&mut find_options))?;
+
+            for delta in diff.deltas() {
+                let old_path = delta
+                    .old_file()
+                    .path()
+                    .map(|x| x.to_string_lossy().into_owned());
+                let new_path = delta
+                    .new_file()
+                    .path()
+                    .map(|x| x.to_string_lossy().into_owned());
+                let (path, old_path) = match delta.status() {
+                    Delta::Renamed | Delta::Copied => (new_path, old_path),
+                    _ => (new_path.or(old_path), None),
+                };
 
-        for entry in self.repo.statuses(Some(&mut options))?.iter() {
-            files.push(entry.path().unwrap().to_string());
+                files.push(FileStatus {
+                    delta: delta.status(),
+                    path: path.unwrap_or_default(),
+                    old_path,
+                    is_staged,
+                });
+            }
         }
 
         Ok(files)
     }
 
     pub fn branch(&self, name: &str, from: Option<&str>) -> Result<String, Error> {
+        self.branch_force(name, from, false)
+    }
+
+    /// Same as `branch` but an existing branch is reset to `from` if `force` is set.
+    pub fn branch_force(
+        &self,
+        name: &str,
+        from: Option<&str>,
+        force: bool,
+    ) -> Result<String, Error> {
         let object = self.repo.revparse_single(from.unwrap_or("HEAD"))?;
         let commit = object.as_commit().unwrap();
-        let branch = self.repo.branch(name, &commit, false)?;
+        let branch = match self.repo.branch(name, commit, force) {
+            Ok(x) => x,
+            Err(err) if err.code() == ErrorCode::Exists => {
+                return Err(Error::new(
+                    ErrorCode::Exists,
+                    ErrorClass::Reference,
+                    format!("a branch named '{}' already exists", name),
+                ))
+            }
+            Err(err) => return Err(err),
+        };
 
         Ok(branch.get().name().unwrap().to_string())
     }
 
+    /// Name of the upstream of the local branch `branch_name` (e.g. `origin/main`), if any.
+    pub fn upstream_of(&self, branch_name: &str) -> Result<Option<String>, Error> {
+        branch_upstream(&self.repo, branch_name)
+    }
+
     pub fn get_branch_hash(&self, branch_name: &str) -> Result<Option<String>, Error> {
         if let (_, Some(reference)) = self.repo.revparse_ext(branch_name)? {
             Ok(Some(format!("{}", reference.target().unwrap())))
@@ -96,6 +277,24 @@ impl Git {
         }
     }
 
+    pub fn blob_at(&self, rev: &str, path: &str) -> Result<Vec<u8>, Error> {
+        let tree = self.repo.revparse_single(rev)?.peel_to_tree()?;
+        let entry = match tree.get_path(Path::new(path)) {
+            Ok(x) => x,
+            Err(err) if err.code() == ErrorCode::NotFound => {
+                return Err(Error::new(
+                    ErrorCode::NotFound,
+                    ErrorClass::Tree,
+                    format!("path '{}' does not exist in '{}'", path, rev),
+                ))
+            }
+            Err(err) => return Err(err),
+        };
+        let blob = entry.to_object(&self.repo)?.peel_to_blob()?;
+
+        Ok(blob.content().to_vec())
+    }
+
     pub fn get_default_branch(&self, remote: &str) -> Result<String, Error> {
         let reference = match self
             .repo
@@ -116,6 +315,67 @@ impl Git {
             .to_string())
     }
 
+    pub fn remote_url(&self, remote: &str) -> Result<String, Error> {
+        let remote = self.repo.find_remote(remote)?;
+
+        match remote.url() {
+            Some(x) => Ok(x.to_string()),
+            None => Err(Error::new(
+                ErrorCode::Invalid,
+                ErrorClass::Config,
+                format!(
+                    "the URL of the remote '{}' is not valid UTF-8",
+                    remote.name().unwrap_or_default()
+                ),
+            )),
+        }
+    }
+
+    /// URL of the page to open a pull request for the branch, if the remote is on a known forge.
+    pub fn compare_url(&self, remote: &str, branch_name: &str) -> Result<Option<String>, Error> {
+        let (host, owner, repo) = match normalize_remote_url(&self.remote_url(remote)?) {
+            Some(x) => x,
+            None => return Ok(None),
+        };
+
+        Ok(if host.contains("gitlab") {
+            Some(format!(
+                "https://{}/{}/{}/-/merge_requests/new?merge_request[source_branch]={}",
+                host, owner, repo, branch_name
+            ))
+        } else if host.contains("github") {
+            Some(format!(
+                "https://{}/{}/{}/compare/{}?expand=1",
+                host, owner, repo, branch_name
+            ))
+        } else {
+            None
+        })
+    }
+
+    pub fn set_branch_config(
+        &self,
+        branch_name: &str,
+        key: &str,
+        value: &str,
+    ) -> Result<(), Error> {
+        self.repo
+            .config()?
+            .set_str(&format!("branch.{}.{}", branch_name, key), value)
+    }
+
+    pub fn get_branch_config(&self, branch_name: &str, key: &str) -> Result<Option<String>, Error> {
+        match self
+            .repo
+            .config()?
+            .get_string(&format!("branch.{}.{}", branch_name, key))
+        {
+            Ok(x) => Ok(Some(x)),
+            Err(err) if err.code() == ErrorCode::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
     pub fn switch_branch(&mut self, branch_name: &str) -> Result<(), Error> {
         let branch = self.repo.find_branch(branch_name, BranchType::Local)?;
         let object = self.repo.revparse_single(branch_name)?;
@@ -124,25 +384,154 @@ impl Git {
         self.repo.set_head(branch.get().name().unwrap())?;
 
         self.branch_name = Some(branch_name.to_string());
+        self.is_detached = false;
         self.head_hash = format!("{}", object.id());
-        if let Ok(upstream) = branch.upstream() {
-            self.upstream = upstream.name()?.map(|x| x.to_string());
+        self.upstream = self.upstream_of(branch_name)?;
+
+        Ok(())
+    }
+
+    /// Point HEAD to the local branch `target`, or to the commit `target` resolves to if `detach`
+    /// is set or if it is not a local branch, and checkout its tree.
+    pub fn set_head(&mut self, target: &str, detach: bool) -> Result<(), Error> {
+        let branch = if detach {
+            None
+        } else {
+            match self.repo.find_branch(target, BranchType::Local) {
+                Ok(x) => Some(x),
+                Err(err) if err.code() == ErrorCode::NotFound => None,
+                Err(err) => return Err(err),
+            }
+        };
+        let commit = match branch.as_ref() {
+            Some(x) => x.get().peel_to_commit()?,
+            None => self.repo.revparse_single(target)?.peel_to_commit()?,
+        };
+
+        self.repo.checkout_tree(commit.as_object(), None)?;
+        if let Some(branch) = branch.as_ref() {
+            self.repo
+                .set_head(branch.get().name().expect("valid UTF-8"))?;
+            self.branch_name = branch.name()?.map(|x| x.to_string());
+            self.upstream = match self.branch_name.as_deref() {
+                Some(name) => self.upstream_of(name)?,
+                None => None,
+            };
+        } else {
+            self.repo.set_head_detached(commit.id())?;
+            self.branch_name = None;
+            self.upstream = None;
         }
 
+        self.is_detached = branch.is_none();
+        self.head_hash = format!("{}", commit.id());
+        self.head_message = commit.message().unwrap_or_default().to_string();
+
         Ok(())
     }
 
-    pub fn commit_files(&mut self, message: &str, files: &[&str]) -> Result<Oid, Error> {
-        let object = self.repo.revparse_single("HEAD")?;
-        let commit = object.as_commit().unwrap();
-        let old_tree = commit.tree()?;
+    pub fn checkout_paths(&self, rev: &str, paths: &[&str], force: bool) -> Result<(), Error> {
+        let object = self.repo.revparse_single(rev)?;
+
+        let mut checkout_builder = git2::build::CheckoutBuilder::new();
+        if force {
+            checkout_builder.force();
+        } else {
+            // NOTE: a safe checkout fails instead of overwriting uncommitted changes
+            checkout_builder.safe();
+        }
+        for path in paths {
+            checkout_builder.path(path);
+        }
+
+        self.repo
+            .checkout_tree(&object, Some(&mut checkout_builder))
+    }
+
+    pub fn commit_files(&mut self, message: &str, files: &[&str]) -> Result<(Oid, Oid), Error> {
+        let (oid, tree_oid) = self.commit_tree_only(message, files)?;
+
+        // NOTE: the working tree already has the committed content, only the index of the
+        //       repository needs to be updated for these files (paths relative to the workdir)
+        let mut index = self.repo.index()?;
+        for file in files {
+            index.add_path(Path::new(file))?;
+        }
+        index.write()?;
+
+        Ok((oid, tree_oid))
+    }
+
+    /// Same as `commit_files` but only HEAD is moved: the index of the repository is not
+    /// updated, the files then appear as staged for the reverse changes in `git status`.
+    pub fn commit_tree_only(&mut self, message: &str, files: &[&str]) -> Result<(Oid, Oid), Error> {
+        let workdir = self
+            .repo
+            .workdir()
+            .ok_or_else(|| Error::from_str("cannot commit files in a bare repository"))?
+            .to_path_buf();
+
+        for file in files {
+            if !workdir.join(file).is_file() {
+                return Err(Error::new(
+                    ErrorCode::NotFound,
+                    ErrorClass::Os,
+                    format!("could not commit '{}': file not found", file),
+                ));
+            }
+        }
 
-        let mut treebuilder = self.repo.treebuilder(Some(&old_tree))?;
+        let mut blobs = Vec::new();
         for file in files {
-            let oid = self.repo.blob_path(Path::new(file))?;
-            treebuilder.insert(file, oid, 0o100644)?;
+            blobs.push((*file, self.repo.blob_path(&workdir.join(file))?));
+        }
+
+        self.commit_blob_oids(message, &blobs)
+    }
+
+    /// Commit the content of the files given in memory: neither the working directory nor the
+    /// index of the repository are touched.
+    pub fn commit_blobs(&mut self, message: &str, entries: &[(&str, &[u8])]) -> Result<Oid, Error> {
+        let mut blobs = Vec::new();
+        for (path, content) in entries {
+            blobs.push((*path, self.repo.blob(content)?));
+        }
+
+        Ok(self.commit_blob_oids(message, &blobs)?.0)
+    }
+
+    fn commit_blob_oids(
+        &mut self,
+        message: &str,
+        blobs: &[(&str, Oid)],
+    ) -> Result<(Oid, Oid), Error> {
+        let commit = self.repo.find_commit(self.head_oid()?)?;
+
+        let mut index = git2::Index::new()?;
+        index.read_tree(&commit.tree()?)?;
+        for (file, oid) in blobs {
+            // NOTE: the mode of the tracked files is kept (e.g. executables), the working
+            //       directory doesn't always have it (e.g. on Windows)
+            let mode = self
+                .tree_entry_mode("HEAD", file)?
+                .map(|x| x as u32)
+                .unwrap_or(0o100644);
+            index.add(&git2::IndexEntry {
+                ctime: git2::IndexTime::new(0, 0),
+                mtime: git2::IndexTime::new(0, 0),
+                dev: 0,
+                ino: 0,
+                mode,
+                uid: 0,
+                gid: 0,
+                file_size: 0,
+                id: *oid,
+                flags: file.len().min(0xfff) as u16,
+                flags_extended: 0,
+                path: file.as_bytes().to_vec(),
+            })?;
         }
-        let tree_oid = treebuilder.write()?;
+        let tree_oid = index.write_tree_to(&self.repo)?;
         let tree = self.repo.find_tree(tree_oid)?;
 
         let signature = self.repo.signature()?;
@@ -155,17 +544,27 @@ impl Git {
             &[&commit],
         )?;
 
-        let mut index = self.repo.index()?;
-        index.update_all(files, None)?;
-        self.repo.checkout_index(Some(&mut index), None)?;
-
         self.head_hash = format!("{}", oid);
 
-        Ok(oid)
+        Ok((oid, tree_oid))
+    }
+
+    /// The file mode of `path` in the tree of `rev` (e.g. `0o100755` for an executable), if the
+    /// path exists.
+    pub fn tree_entry_mode(&self, rev: &str, path: &str) -> Result<Option<i32>, Error> {
+        let tree = self.repo.revparse_single(rev)?.peel_to_tree()?;
+
+        match tree.get_path(Path::new(path)) {
+            Ok(entry) => Ok(Some(entry.filemode())),
+            Err(err) if err.code() == ErrorCode::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
     }
 
+    /// Only the changes of the tracked files (staged or not) are considered, use
+    /// `is_clean_worktree` to also consider the untracked files.
     pub fn has_file_changes(&self) -> Result<bool, Error> {
-        let tree = self.repo.head()?.peel_to_tree()?;
+        let tree = self.repo.find_commit(self.head_oid()?)?.tree()?;
 
         Ok(self
             .repo
@@ -175,105 +574,384 @@ impl Git {
             > 0)
     }
 
-    pub fn check_no_conflict(&mut self, branch_name: &str) -> Result<Option<bool>, Error> {
-        let mut cargo_lock_conflict = false;
-        let our_object = self.repo.revparse_single("HEAD")?;
-        let our = our_object.as_commit().expect("our is a commit");
-        let their_object = self.repo.revparse_single(branch_name)?;
-        let their = their_object.as_commit().expect("their is a commit");
+    /// The untracked files are considered unless `status.showUntrackedFiles` is set to `no`.
+    pub fn is_clean_worktree(&self) -> Result<bool, Error> {
+        let show_untracked = self
+            .config
+            .get_string("status.showUntrackedFiles")
+            .map(|x| x != "no")
+            .unwrap_or(true);
+
+        let mut options = StatusOptions::new();
+        options.include_untracked(show_untracked);
+        options.recurse_untracked_dirs(show_untracked);
+        options.include_ignored(false);
+        options.exclude_submodules(true);
+
+        Ok(self.repo.statuses(Some(&mut options))?.is_empty())
+    }
 
+    fn merge_options(&self) -> MergeOptions {
         let mut options = MergeOptions::new();
         options.fail_on_conflict(false);
+        options.ignore_whitespace(self.merge_ignore_whitespace);
 
-        let index = self.repo.merge_commits(&our, &their, Some(&options))?;
-        let conflicts = index.conflicts()?.collect::<Result<Vec<_>, _>>()?;
-        for conflict in conflicts {
-            let their = conflict.their.expect("an index entry for their exist");
-            let path = std::str::from_utf8(their.path.as_slice()).expect("valid UTF-8");
+        options
+    }
+
+    pub fn try_merge_index(&self, our: &str, their: &str) -> Result<MergeAttempt, Error> {
+        let our = if our == "HEAD" {
+            self.repo.find_commit(self.head_oid()?)?
+        } else {
+            self.repo.revparse_single(our)?.peel_to_commit()?
+        };
+        let their = self.repo.revparse_single(their)?.peel_to_commit()?;
+
+        let index = self
+            .repo
+            .merge_commits(&our, &their, Some(&self.merge_options()))?;
+        let mut conflicts = Vec::new();
+        for conflict in index.conflicts()? {
+            let conflict = conflict?;
+            let path = conflict
+                .our
+                .as_ref()
+                .or(conflict.their.as_ref())
+                .or(conflict.ancestor.as_ref())
+                .map(|x| String::from_utf8_lossy(&x.path).into_owned())
+                .expect("a conflict has at least one side");
+
+            conflicts.push(ConflictEntry {
+                path,
+                ancestor: conflict.ancestor,
+                our: conflict.our,
+                their: conflict.their,
+            });
+        }
 
-            if path == "Cargo.lock" {
-                cargo_lock_conflict = true;
+        Ok(MergeAttempt { index, conflicts })
+    }
+
+    /// The conflicts that `merge_no_conflict` would ignore (or regenerate) with the same
+    /// arguments, `None` if a conflict blocks the merge. (Nothing is written.)
+    pub fn check_no_conflict(
+        &self,
+        branch_name: &str,
+        ignore_conflict_globs: &GlobSet,
+        resolution: ConflictResolution,
+        regenerate_commands: &[(GlobMatcher, String)],
+    ) -> Result<Option<Vec<String>>, Error> {
+        let mut ignored_conflicts = Vec::new();
+
+        for conflict in self.try_merge_index("HEAD", branch_name)?.conflicts {
+            let regenerate = regenerate_commands
+                .iter()
+                .any(|(glob, _)| glob.is_match(&conflict.path));
+            let kept_side = if regenerate || resolution == ConflictResolution::Theirs {
+                &conflict.their
             } else {
+                &conflict.our
+            };
+            if kept_side.is_none() || !regenerate && !ignore_conflict_globs.is_match(&conflict.path)
+            {
                 return Ok(None);
             }
+            ignored_conflicts.push(conflict.path);
+        }
+
+        Ok(Some(ignored_conflicts))
+    }
+
+    pub fn list_conflicts(&self, our: &str, their: &str) -> Result<Vec<String>, Error> {
+        Ok(self
+            .try_merge_index(our, their)?
+            .conflicts
+            .into_iter()
+            .map(|x| x.path)
+            .collect())
+    }
+
+    /// The globs of the multivar `name` (unless `only_extra` is set) and the extra globs.
+    pub fn glob_set(
+        &self,
+        name: &str,
+        extra: &[String],
+        only_extra: bool,
+    ) -> Result<GlobSet, Error> {
+        let glob = |x: &str| Glob::new(x).map_err(|e| Error::from_str(&e.to_string()));
+
+        let mut builder = GlobSetBuilder::new();
+        if !only_extra {
+            for entry in self
+                .config
+                .multivar(name, None)
+                .iter()
+                .flatten()
+                .filter_map(|x| x.ok())
+            {
+                builder.add(glob(entry.value().expect("invalid UTF-8"))?);
+            }
+        }
+        for x in extra {
+            builder.add(glob(x)?);
         }
 
-        Ok(Some(cargo_lock_conflict))
+        builder.build().map_err(|e| Error::from_str(&e.to_string()))
     }
 
+    /// The commands of `try-merge.regenerate.<glob>`: the glob is the rest of the key after the
+    /// prefix.
+    ///
+    /// NOTE: git lowercases the part after the last dot, the extension of the glob is matched in
+    ///       lowercase
+    pub fn regenerate_commands(&self) -> Result<Vec<(GlobMatcher, String)>, Error> {
+        const PREFIX: &str = "try-merge.regenerate.";
+
+        let mut commands = Vec::new();
+        for entry in self
+            .config
+            .entries(Some("^try-merge\\.regenerate\\."))
+            .iter()
+            .flatten()
+            .filter_map(|x| x.ok())
+        {
+            let name = entry.name().expect("invalid UTF-8");
+            let glob =
+                Glob::new(&name[PREFIX.len()..]).map_err(|e| Error::from_str(&e.to_string()))?;
+            commands.push((
+                glob.compile_matcher(),
+                entry.value().expect("invalid UTF-8").to_string(),
+            ));
+        }
+
+        Ok(commands)
+    }
+
+    /// `try-merge.conflict-resolution`, the upstream version by default.
+    pub fn conflict_resolution(&self) -> Result<ConflictResolution, Error> {
+        match self.config.get_string("try-merge.conflict-resolution") {
+            Ok(value) => value.parse(),
+            Err(err) if err.code() == ErrorCode::NotFound => Ok(ConflictResolution::Theirs),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// The merged tree is checked out before the merge commit is made. Unless `force_checkout`
+    /// is set, it fails without committing if local changes would be overwritten.
+    ///
+    /// The ignored conflicts are resolved with the side of `resolution`, the files to regenerate
+    /// are always taken from their side.
+    ///
+    /// The merged revision is the first parent of the merge commit if `upstream_first` is set.
+    #[allow(clippy::too_many_arguments)]
     pub fn merge_no_conflict(
         &mut self,
         branch_name: &str,
         message: &str,
         ignore_conflict_globs: &GlobSet,
-    ) -> Result<Option<(String, Vec<String>)>, Error> {
-        let our_object = self.repo.revparse_single("HEAD")?;
-        let our = our_object.as_commit().expect("our is a commit");
-        let their_object = self.repo.revparse_single(branch_name)?;
-        let their = their_object.as_commit().expect("their is a commit");
-
-        let mut options = MergeOptions::new();
-        options.fail_on_conflict(false);
-
-        let mut index = self.repo.merge_commits(&our, &their, Some(&mut options))?;
-        let conflicts = index.conflicts()?.collect::<Result<Vec<_>, _>>()?;
+        resolution: ConflictResolution,
+        regenerate_commands: &[(GlobMatcher, String)],
+        force_checkout: bool,
+        preserve_author: bool,
+        upstream_first: bool,
+    ) -> Result<Option<MergeReport>, Error> {
+        let our_commit = self.repo.find_commit(self.head_oid()?)?;
+        let our = &our_commit;
+        // NOTE: the revision can be an annotated tag
+        let their_commit = self.repo.revparse_single(branch_name)?.peel_to_commit()?;
+        let their = &their_commit;
+
+        let MergeAttempt {
+            mut index,
+            conflicts,
+        } = self.try_merge_index("HEAD", branch_name)?;
         let mut ignored_conflicts = Vec::new();
+        let mut regenerated = Vec::new();
+        let mut commands = Vec::new();
         for conflict in conflicts {
-            let their = match conflict.their {
+            // NOTE: the files to regenerate are taken from their side before running the commands
+            let path = conflict.path;
+            let matching_commands = regenerate_commands
+                .iter()
+                .filter(|(glob, _)| glob.is_match(&path))
+                .map(|(_, command)| command)
+                .collect::<Vec<_>>();
+            let regenerate = !matching_commands.is_empty();
+            if !regenerate && ignore_conflict_globs.matches(&path).is_empty() {
+                return Ok(None);
+            }
+            let entry = if regenerate || resolution == ConflictResolution::Theirs {
+                conflict.their
+            } else {
+                conflict.our
+            };
+            let entry = match entry {
                 Some(x) => x,
+                // NOTE: the file has been deleted on this side
                 None => return Ok(None),
             };
+            for command in matching_commands {
+                if !commands.contains(&command) {
+                    commands.push(command);
+                }
+            }
 
-            let path = std::str::from_utf8(their.path.as_slice()).expect("valid UTF-8");
-
-            if ignore_conflict_globs.matches(path).is_empty() {
-                return Ok(None);
-            } else {
-                use bitvec::prelude::*;
+            use bitvec::prelude::*;
 
-                ignored_conflicts.push(path.to_owned());
+            let mut flags = BitVec::<Msb0, _>::from_element(entry.flags);
+            // NOTE: Reset stage flags
+            // https://github.com/git/git/blob/master/Documentation/technical/index-format.txt
+            flags[2..=3].set_all(false);
+            let entry = git2::IndexEntry {
+                flags: flags.as_slice()[0],
+                ..entry
+            };
+            index.remove_path(Path::new(&path))?;
+            index.add(&entry)?;
 
-                let mut flags = BitVec::<Msb0, _>::from_element(their.flags);
-                // NOTE: Reset stage flags
-                // https://github.com/git/git/blob/master/Documentation/technical/index-format.txt
-                flags[2..=3].set_all(false);
-                let their = git2::IndexEntry {
-                    flags: flags.as_slice()[0],
-                    ..their
-                };
-                index.remove_path(Path::new("Cargo.lock")).unwrap();
-                index.add(&their)?;
+            if regenerate {
+                regenerated.push((path.clone(), entry));
             }
+            ignored_conflicts.push(path);
         }
 
         let oid = index.write_tree_to(&self.repo)?;
-        let tree = self.repo.find_tree(oid)?;
+        let mut tree = self.repo.find_tree(oid)?;
+
+        let mut checkout_builder = git2::build::CheckoutBuilder::new();
+        if force_checkout {
+            checkout_builder.force();
+        } else {
+            checkout_builder.safe();
+        }
+        self.repo
+            .checkout_tree(tree.as_object(), Some(&mut checkout_builder))?;
+
+        if !commands.is_empty() {
+            if let Err(err) = self.run_regenerate_commands(&commands) {
+                // NOTE: restore the working directory, nothing has been committed yet
+                self.repo.checkout_tree(
+                    our.as_object(),
+                    Some(git2::build::CheckoutBuilder::new().force()),
+                )?;
+                return Err(err);
+            }
+
+            let workdir = self.repo.workdir().expect("not a bare repository");
+            for (path, entry) in regenerated {
+                match std::fs::read(workdir.join(&path)) {
+                    Ok(content) => index.add(&IndexEntry {
+                        id: self.repo.blob(&content)?,
+                        file_size: content.len() as u32,
+                        ..entry
+                    })?,
+                    Err(_) => index.remove_path(Path::new(&path))?,
+                }
+            }
+
+            let oid = index.write_tree_to(&self.repo)?;
+            tree = self.repo.find_tree(oid)?;
+
+            let mut repo_index = self.repo.index()?;
+            repo_index.read_tree(&tree)?;
+            repo_index.write()?;
+        }
 
         let signature = self.repo.signature()?;
-        let oid = self.repo.commit(
-            Some("HEAD"),
-            &signature,
-            &signature,
-            message,
-            &tree,
-            &[&our, &their],
+        // NOTE: the committer is always the current user
+        let author = if preserve_author {
+            their.author()
+        } else {
+            signature.clone()
+        };
+        let parents = if upstream_first {
+            [their, our]
+        } else {
+            [our, their]
+        };
+        // NOTE: HEAD is updated afterwards, libgit2 refuses to update a reference that is not the
+        //       first parent
+        let oid = self
+            .repo
+            .commit(None, &author, &signature, message, &tree, &parents)?;
+        self.repo.head()?.set_target(
+            oid,
+            &format!(
+                "commit (merge): {}",
+                message.lines().next().unwrap_or_default()
+            ),
         )?;
 
-        let mut checkout_builder = git2::build::CheckoutBuilder::new();
-        checkout_builder.force();
-        self.repo.checkout_head(Some(&mut checkout_builder))?;
-
         self.head_hash = format!("{}", oid);
 
-        Ok(Some((self.head_hash.clone(), ignored_conflicts)))
+        Ok(Some(MergeReport {
+            head_hash: self.head_hash.clone(),
+            ignored_conflicts,
+            merged_oid: their.id(),
+        }))
+    }
+
+    /// Merge `branch_name` in the working directory like `git merge --no-commit`: the conflicts
+    /// are written in the files and in the index and the merge stays in progress until it is
+    /// committed. Returns the conflicting paths.
+    pub fn merge_in_worktree(
+        &self,
+        branch_name: &str,
+        message: &str,
+    ) -> Result<Vec<String>, Error> {
+        let their = self.repo.revparse_single(branch_name)?.peel_to_commit()?;
+        let annotated = self.repo.find_annotated_commit(their.id())?;
+
+        self.repo
+            .merge(&[&annotated], Some(&mut self.merge_options()), None)?;
+        std::fs::write(self.repo.path().join("MERGE_MSG"), message)
+            .map_err(|e| Error::from_str(&e.to_string()))?;
+
+        let mut conflicts = Vec::new();
+        for conflict in self.repo.index()?.conflicts()? {
+            let conflict = conflict?;
+            if let Some(entry) = conflict.our.or(conflict.their).or(conflict.ancestor) {
+                conflicts.push(String::from_utf8_lossy(&entry.path).into_owned());
+            }
+        }
+
+        Ok(conflicts)
+    }
+
+    fn run_regenerate_commands(&self, commands: &[&String]) -> Result<(), Error> {
+        let workdir = self.repo.workdir().expect("not a bare repository");
+
+        for command in commands {
+            let output = std::process::Command::new("sh")
+                .arg("-c")
+                .arg(command.as_str())
+                .current_dir(workdir)
+                .output()
+                .map_err(|e| Error::from_str(&e.to_string()))?;
+
+            if !output.status.success() {
+                return Err(Error::new(
+                    ErrorCode::GenericError,
+                    ErrorClass::Merge,
+                    format!(
+                        "Command `{}` failed, the merge has been aborted:\n{}{}",
+                        command,
+                        String::from_utf8_lossy(&output.stdout),
+                        String::from_utf8_lossy(&output.stderr),
+                    ),
+                ));
+            }
+        }
+
+        Ok(())
     }
 
     pub fn rev_list(&self, from: &str, to: &str, reversed: bool) -> Result<Vec<String>, Error> {
         let mut revwalk = self.repo.revwalk()?;
         if reversed {
-            revwalk.set_sorting(Sort::TOPOLOGICAL | Sort::REVERSE);
+            revwalk.set_sorting(Sort::TOPOLOGICAL | Sort::REVERSE)?;
         } else {
-            revwalk.set_sorting(Sort::TOPOLOGICAL);
+            revwalk.set_sorting(Sort::TOPOLOGICAL)?;
         }
 
         let from_object = self.repo.revparse_single(from)?;
@@ -286,31 +964,646 @@ impl Git {
             .collect::<Result<Vec<_>, Error>>()
     }
 
-    pub fn update_upstream(&self, branch_name: &str) -> Result<(), Error> {
-        let branch = self.repo.find_branch(branch_name, BranchType::Remote)?;
-        let (maybe_remote_name, branch_name) = get_remote_and_branch(&branch);
+    /// Number of commits listed by `rev_list`, without formatting them.
+    pub fn count_commits_between(&self, from: &str, to: &str) -> Result<usize, Error> {
+        let mut revwalk = self.repo.revwalk()?;
 
-        // TODO: this method fails if branch_name is not a remote branch
-        //       this `if` statement makes no sense
-        if let Some(remote_name) = maybe_remote_name {
-            let mut remote_callbacks = RemoteCallbacks::new();
-            let mut handler = CredentialHandler::new();
-            remote_callbacks.credentials(move |x, y, z| handler.credentials_callback(x, y, z));
+        let from_object = self.repo.revparse_single(from)?;
+        let to_object = self.repo.revparse_single(to)?;
+        revwalk.hide(from_object.id())?;
+        revwalk.push(to_object.id())?;
 
-            let mut fetch_options = FetchOptions::new();
-            fetch_options.remote_callbacks(remote_callbacks);
+        revwalk.try_fold(0, |count, x| x.map(|_| count + 1))
+    }
 
-            self.repo.find_remote(remote_name)?.fetch(
-                &[branch_name],
-                Some(&mut fetch_options),
-                None,
-            )?;
+    pub fn rev_list_paths(
+        &self,
+        from: &str,
+        to: &str,
+        reversed: bool,
+        paths: &[&str],
+    ) -> Result<Vec<String>, Error> {
+        let mut options = DiffOptions::new();
+        for path in paths {
+            options.pathspec(path);
         }
 
-        Ok(())
-    }
+        let mut rev_list = Vec::new();
+        for revision in self.rev_list(from, to, reversed)? {
+            let commit = self.repo.find_commit(Oid::from_str(&revision)?)?;
+            let parent_tree = commit.parent(0).ok().map(|x| x.tree()).transpose()?;
+            let diff = self.repo.diff_tree_to_tree(
+                parent_tree.as_ref(),
+                Some(&commit.tree()?),
+                Some(&mut options),
+            )?;
+
+            if diff.deltas().len() > 0 {
+                rev_list.push(revision);
+            }
+        }
+
+        Ok(rev_list)
+    }
+
+    /// Local branches whose last commit is in the history of `target`.
+    pub fn merged_branches(&self, target: &str) -> Result<Vec<String>, Error> {
+        let target = self.repo.revparse_single(target)?.peel_to_commit()?.id();
+
+        let mut branches = Vec::new();
+        for branch in self.repo.branches(Some(BranchType::Local))? {
+            let (branch, _) = branch?;
+            let oid = branch.get().peel_to_commit()?.id();
+
+            if oid == target || self.repo.graph_descendant_of(target, oid)? {
+                if let Some(name) = branch.name()? {
+                    branches.push(name.to_string());
+                }
+            }
+        }
+
+        Ok(branches)
+    }
+
+    pub fn ahead_behind(&self, local: &str, upstream: &str) -> Result<(usize, usize), Error> {
+        let local = self.repo.revparse_single(local)?.peel_to_commit()?;
+        let upstream = self.repo.revparse_single(upstream)?.peel_to_commit()?;
+
+        self.repo.graph_ahead_behind(local.id(), upstream.id())
+    }
+
+    /// Position of the current branch relatively to its upstream.
+    pub fn upstream_status(&self) -> Result<UpstreamStatus, Error> {
+        let upstream = match (self.branch_name.as_ref(), self.upstream.as_ref()) {
+            (None, _) => return Ok(UpstreamStatus::NoBranch),
+            (Some(_), None) => return Ok(UpstreamStatus::NoUpstream),
+            (Some(_), Some(upstream)) => upstream,
+        };
+
+        Ok(match self.ahead_behind("HEAD", upstream)? {
+            (0, 0) => UpstreamStatus::UpToDate,
+            (ahead, 0) => UpstreamStatus::Ahead(ahead),
+            (0, behind) => UpstreamStatus::Behind(behind),
+            (ahead, behind) => UpstreamStatus::Diverged(ahead, behind),
+        })
+    }
+
+    /// `true` if `maybe_ancestor` is reachable from `descendant`, including when they are the same
+    /// commit.
+    pub fn is_ancestor(&self, maybe_ancestor: &str, descendant: &str) -> Result<bool, Error> {
+        let ancestor = self
+            .repo
+            .revparse_single(maybe_ancestor)?
+            .peel_to_commit()?;
+        let descendant = self.repo.revparse_single(descendant)?.peel_to_commit()?;
+
+        Ok(ancestor.id() == descendant.id()
+            || self
+                .repo
+                .graph_descendant_of(descendant.id(), ancestor.id())?)
+    }
+
+    pub fn merge_base(&self, one: &str, two: &str) -> Result<Option<String>, Error> {
+        let one = self.repo.revparse_single(one)?.peel_to_commit()?;
+        let two = self.repo.revparse_single(two)?.peel_to_commit()?;
+
+        match self.repo.merge_base(one.id(), two.id()) {
+            Ok(oid) => Ok(Some(format!("{}", oid))),
+            Err(err) if err.code() == ErrorCode::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Name of the revision based on the closest tag (e.g. v1.2.3-5-gabcdef0), or the
+    /// abbreviated hash if there is no tag in its history.
+    pub fn describe(&self, rev: &str) -> Result<String, Error> {
+        let object = self.repo.revparse_single(rev)?.peel(ObjectType::Commit)?;
+
+        let describe = object.describe(
+            git2::DescribeOptions::new()
+                .describe_tags()
+                .show_commit_oid_as_fallback(true),
+        )?;
+
+        describe.format(None)
+    }
+
+    pub fn diff_name_status(&self, from: &str, to: &str) -> Result<Vec<(Delta, String)>, Error> {
+        let from_tree = self.repo.revparse_single(from)?.peel_to_tree()?;
+        let to_tree = self.repo.revparse_single(to)?.peel_to_tree()?;
+
+        let mut diff = self
+            .repo
+            .diff_tree_to_tree(Some(&from_tree), Some(&to_tree), None)?;
+        diff.find_similar(None)?;
+
+        Ok(diff
+            .deltas()
+            .map(|delta| {
+                let old_path = delta.old_file().path().map(|x| x.to_string_lossy());
+                let new_path = delta.new_file().path().map(|x| x.to_string_lossy());
+                let path = match (delta.status(), old_path, new_path) {
+                    // NOTE: same format as `git status`
+                    (Delta::Renamed, Some(old), Some(new))
+                    | (Delta::Copied, Some(old), Some(new)) => {
+                        format!("{} -> {}", old, new)
+                    }
+                    (_, _, Some(path)) | (_, Some(path), None) => path.into_owned(),
+                    (_, None, None) => String::new(),
+                };
+
+                (delta.status(), path)
+            })
+            .collect())
+    }
+
+    pub fn update_upstream(&self, branch_name: &str) -> Result<(), Error> {
+        let branch = self.repo.find_branch(branch_name, BranchType::Remote)?;
+        let (maybe_remote_name, branch_name) = get_remote_and_branch(&branch);
+
+        // TODO: this method fails if branch_name is not a remote branch
+        //       this `if` statement makes no sense
+        if let Some(remote_name) = maybe_remote_name {
+            let mut fetch_options = FetchOptions::new();
+            fetch_options.remote_callbacks(remote_callbacks(self.repo.config()?));
+
+            self.repo.find_remote(remote_name)?.fetch(
+                &[branch_name],
+                Some(&mut fetch_options),
+                None,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Fetch all the remotes with their configured refspecs. A remote that cannot be fetched does
+    /// not prevent fetching the others, the errors are reported together at the end.
+    pub fn fetch_all(&self) -> Result<(), Error> {
+        let mut errors = Vec::new();
+        for remote_name in self.repo.remotes()?.iter().flatten() {
+            let mut fetch_options = FetchOptions::new();
+            fetch_options.remote_callbacks(remote_callbacks(self.repo.config()?));
+
+            let res = self
+                .repo
+                .find_remote(remote_name)
+                .and_then(|mut remote| remote.fetch::<&str>(&[], Some(&mut fetch_options), None));
+            if let Err(err) = res {
+                errors.push(format!("{} ({})", remote_name, err.message()));
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::from_str(&format!(
+                "could not fetch {}",
+                errors.join(", ")
+            )))
+        }
+    }
+
+    /// Fetch `name` if it is a branch of a remote (e.g. `origin/main`), even if it has never been
+    /// fetched. Returns `false` without fetching if it is not prefixed by the name of a remote
+    /// (local branches, tags, hashes).
+    pub fn fetch_remote_branch(&self, name: &str) -> Result<bool, Error> {
+        let remote_name = match find_remote_name(&self.repo, &format!("refs/remotes/{}", name))? {
+            Some(x) => x,
+            None => return Ok(false),
+        };
+        let branch_name = &name[remote_name.len() + 1..];
+
+        let mut fetch_options = FetchOptions::new();
+        fetch_options.remote_callbacks(remote_callbacks(self.repo.config()?));
+
+        // NOTE: an explicit refspec also updates the branches not covered by the configuration
+        let refspec = format!(
+            "+refs/heads/{}:refs/remotes/{}/{}",
+            branch_name, remote_name, branch_name
+        );
+        self.repo
+            .find_remote(&remote_name)?
+            .fetch(&[&refspec], Some(&mut fetch_options), None)?;
 
-    pub fn ancestors(&self, rev: &str) -> Result<Ancestors, Error> {
+        Ok(true)
+    }
+
+    /// Point `refs/remotes/<remote>/HEAD` to the current default branch of the remote. Returns
+    /// the new target, `None` if the remote has no default branch.
+    pub fn update_remote_head(&self, remote_name: &str) -> Result<Option<String>, Error> {
+        let mut remote = self.repo.find_remote(remote_name)?;
+
+        let default_branch = {
+            let connection = remote.connect_auth(
+                Direction::Fetch,
+                Some(remote_callbacks(self.repo.config()?)),
+                None,
+            )?;
+            match connection.default_branch() {
+                Ok(x) => x.as_str().map(|x| x.to_string()),
+                Err(err) if err.code() == ErrorCode::NotFound => None,
+                Err(err) => return Err(err),
+            }
+        };
+        let default_branch = match default_branch {
+            Some(x) => x,
+            None => return Ok(None),
+        };
+
+        let mut target = None;
+        for refspec in remote.refspecs() {
+            if matches!(refspec.direction(), Direction::Fetch)
+                && refspec.src_matches(&default_branch)
+            {
+                target = refspec
+                    .transform(&default_branch)?
+                    .as_str()
+                    .map(|x| x.to_string());
+                break;
+            }
+        }
+        let target = match target {
+            Some(x) => x,
+            None => return Ok(None),
+        };
+
+        self.repo.reference_symbolic(
+            &format!("refs/remotes/{}/HEAD", remote_name),
+            &target,
+            true,
+            "update remote HEAD",
+        )?;
+
+        Ok(Some(target))
+    }
+
+    pub fn prune_remote(&self, remote_name: &str) -> Result<Vec<String>, Error> {
+        let mut remote = self.repo.find_remote(remote_name)?;
+
+        let remote_heads = {
+            let connection = remote.connect_auth(
+                Direction::Fetch,
+                Some(remote_callbacks(self.repo.config()?)),
+                None,
+            )?;
+            connection
+                .list()?
+                .iter()
+                .map(|x| x.name().to_string())
+                .collect::<HashSet<_>>()
+        };
+
+        let mut stale_refs = Vec::new();
+        for reference in self.repo.references()? {
+            let reference = reference?;
+            let name = match (reference.kind(), reference.name()) {
+                (Some(ReferenceType::Direct), Some(name)) => name,
+                _ => continue,
+            };
+
+            let mut tracked = false;
+            let mut exists = false;
+            for refspec in remote.refspecs() {
+                if !matches!(refspec.direction(), Direction::Fetch) || !refspec.dst_matches(name) {
+                    continue;
+                }
+                tracked = true;
+                if let Some(src) = refspec.rtransform(name)?.as_str() {
+                    exists |= remote_heads.contains(src);
+                }
+            }
+
+            if tracked && !exists {
+                stale_refs.push(name.to_string());
+            }
+        }
+
+        for name in stale_refs.iter() {
+            self.repo.find_reference(name)?.delete()?;
+        }
+
+        Ok(stale_refs)
+    }
+
+    /// Delete the local branch `branch_name` and, if `delete_remote` is set, its upstream on the
+    /// remote. The current branch and the default branch of the remote cannot be deleted.
+    ///
+    /// If `remote_name` is given, the branch of the same name is deleted on this remote instead
+    /// of the upstream, even if it is not tracked.
+    pub fn delete_branch(
+        &mut self,
+        branch_name: &str,
+        delete_remote: bool,
+        remote_name: Option<&str>,
+    ) -> Result<DeleteOutcome, Error> {
+        let mut branch = match self.repo.find_branch(branch_name, BranchType::Local) {
+            Ok(x) => x,
+            Err(err) if err.code() == ErrorCode::NotFound => {
+                return Err(Error::new(
+                    ErrorCode::NotFound,
+                    ErrorClass::Reference,
+                    format!("Could not find local branch: {}", branch_name),
+                ))
+            }
+            Err(err) => return Err(err),
+        };
+        let branch_name = branch.name()?.expect("not valid utf-8").to_string();
+
+        if branch.is_head() {
+            return Err(Error::from_str(
+                "Aborted: cannot delete branch currently pointed at by HEAD",
+            ));
+        }
+
+        let mut remote = RemoteDeletion::None;
+        if let Some(remote_name) = remote_name {
+            let tracking_name = format!("refs/remotes/{}/{}", remote_name, branch_name);
+            remote = if self.delete_remote_branch(remote_name, &branch_name)? {
+                RemoteDeletion::Deleted(tracking_name)
+            } else {
+                RemoteDeletion::AlreadyDeleted(tracking_name)
+            };
+        } else if delete_remote {
+            let upstream_remote = self.get_branch_config(&branch_name, "remote")?;
+            if upstream_remote.as_deref() == Some(".") {
+                // NOTE: the upstream is a local branch, it is not deleted
+            } else if let Some(upstream) = self.upstream_of(&branch_name)? {
+                let upstream_name = format!("refs/remotes/{}", upstream);
+                match find_remote_name(&self.repo, &upstream_name)? {
+                    Some(remote_name) => {
+                        let remote_branch_name = upstream_name
+                            .strip_prefix(&format!("refs/remotes/{}/", remote_name))
+                            .expect("the upstream is in the namespace of the remote");
+
+                        remote = if self.delete_remote_branch(&remote_name, remote_branch_name)? {
+                            RemoteDeletion::Deleted(upstream_name)
+                        } else {
+                            RemoteDeletion::AlreadyDeleted(upstream_name)
+                        };
+                    }
+                    // NOTE: the remote has been removed but not the remote-tracking branch
+                    None => remote = RemoteDeletion::RemoteGone(upstream_name),
+                }
+            } else if let Some(remote_name) = upstream_remote {
+                // NOTE: the upstream doesn't resolve when its remote has been removed
+                remote = RemoteDeletion::RemoteGone(remote_name);
+            }
+        }
+
+        branch.delete()?;
+
+        Ok(DeleteOutcome {
+            branch_name,
+            remote,
+        })
+    }
+
+    /// Push the local branch `branch_name` to `remote_name` and set it as its upstream.
+    pub fn push_set_upstream(&mut self, remote_name: &str, branch_name: &str) -> Result<(), Error> {
+        // NOTE: the rejected references are not errors of the push itself
+        let mut rejected = None;
+        let mut remote = self.repo.find_remote(remote_name)?;
+
+        let mut remote_callbacks = remote_callbacks(self.repo.config()?);
+        remote_callbacks.push_update_reference(|name, status| {
+            if let Some(status) = status {
+                rejected = Some(format!("{} rejected: {}", name, status));
+            }
+            Ok(())
+        });
+
+        let mut push_options = PushOptions::new();
+        push_options.remote_callbacks(remote_callbacks);
+
+        let refspec = format!("refs/heads/{}:refs/heads/{}", branch_name, branch_name);
+        remote.push(&[&refspec], Some(&mut push_options))?;
+        drop(push_options);
+        if let Some(message) = rejected {
+            return Err(Error::from_str(&message));
+        }
+
+        let mut branch = self.repo.find_branch(branch_name, BranchType::Local)?;
+        branch.set_upstream(Some(&format!("{}/{}", remote_name, branch_name)))?;
+        if self.branch_name.as_deref() == Some(branch_name) {
+            self.upstream = self.upstream_of(branch_name)?;
+        }
+
+        Ok(())
+    }
+
+    /// Delete the branch `remote_branch_name` on the remote and its remote-tracking branch.
+    /// Returns `false` if the branch was already deleted on the remote.
+    pub fn delete_remote_branch(
+        &self,
+        remote_name: &str,
+        remote_branch_name: &str,
+    ) -> Result<bool, Error> {
+        let mut remote = self.repo.find_remote(remote_name)?;
+        let tracking_name = format!("refs/remotes/{}/{}", remote_name, remote_branch_name);
+
+        // NOTE: the remote-tracking HEAD may be outdated or missing (e.g. a remote never fetched),
+        //       the default branch of the remote itself is checked too
+        let is_default_branch = match self
+            .repo
+            .find_reference(&format!("refs/remotes/{}/HEAD", remote_name))
+        {
+            Ok(reference) => reference.symbolic_target() == Some(tracking_name.as_str()),
+            Err(err) if err.code() == ErrorCode::NotFound => false,
+            Err(err) => return Err(err),
+        };
+
+        let remote_ref = format!("refs/heads/{}", remote_branch_name);
+        let (remote_exists, remote_default_branch) = {
+            let connection = remote.connect_auth(
+                Direction::Fetch,
+                Some(remote_callbacks(self.repo.config()?)),
+                None,
+            )?;
+            let default_branch = match connection.default_branch() {
+                Ok(x) => x.as_str().map(|x| x.to_string()),
+                Err(err) if err.code() == ErrorCode::NotFound => None,
+                Err(err) => return Err(err),
+            };
+
+            (
+                connection.list()?.iter().any(|x| x.name() == remote_ref),
+                default_branch,
+            )
+        };
+
+        if is_default_branch || remote_default_branch.as_deref() == Some(remote_ref.as_str()) {
+            return Err(Error::from_str(
+                "Aborted: deleting default branch is forbidden",
+            ));
+        }
+
+        if remote_exists {
+            let mut push_options = PushOptions::new();
+            push_options.remote_callbacks(remote_callbacks(self.repo.config()?));
+
+            remote.push(&[&format!("+:{}", remote_ref)], Some(&mut push_options))?;
+        }
+
+        // NOTE: the remote-tracking branch is stale or not deleted by the push
+        match self.repo.find_reference(&tracking_name) {
+            Ok(mut reference) => reference.delete()?,
+            Err(err) if err.code() == ErrorCode::NotFound => {}
+            Err(err) => return Err(err),
+        }
+
+        Ok(remote_exists)
+    }
+
+    pub fn reflog(&self, ref_name: &str) -> Result<Vec<ReflogEntry>, Error> {
+        Ok(self
+            .repo
+            .reflog(ref_name)?
+            .iter()
+            .map(|entry| ReflogEntry {
+                old_oid: entry.id_old(),
+                new_oid: entry.id_new(),
+                message: entry.message().map(|x| x.to_string()),
+                time: entry.committer().when(),
+                committer: entry.committer().to_string(),
+            })
+            .collect())
+    }
+
+    pub fn submodule_status(&self) -> Result<Vec<SubmoduleInfo>, Error> {
+        let mut submodules = Vec::new();
+        for submodule in self.repo.submodules()? {
+            let name = submodule.name().expect("valid UTF-8").to_string();
+            let status = self
+                .repo
+                .submodule_status(&name, git2::SubmoduleIgnore::None)?;
+
+            submodules.push(SubmoduleInfo {
+                path: submodule.path().to_string_lossy().into_owned(),
+                name,
+                is_dirty: status.intersects(
+                    git2::SubmoduleStatus::WD_MODIFIED
+                        | git2::SubmoduleStatus::WD_INDEX_MODIFIED
+                        | git2::SubmoduleStatus::WD_WD_MODIFIED
+                        | git2::SubmoduleStatus::WD_UNTRACKED,
+                ),
+                is_initialized: !status.contains(git2::SubmoduleStatus::WD_UNINITIALIZED),
+            });
+        }
+
+        Ok(submodules)
+    }
+
+    /// Warn when a submodule is not initialized or has uncommitted changes, fail instead if
+    /// `strict` is set.
+    pub fn check_submodules(&self, strict: bool) -> Result<(), Error> {
+        for submodule in self.submodule_status()? {
+            let problem = if !submodule.is_initialized {
+                "is not initialized"
+            } else if submodule.is_dirty {
+                "has uncommitted changes"
+            } else {
+                continue;
+            };
+
+            if strict {
+                return Err(Error::from_str(&format!(
+                    "Submodule {} {}, aborting.",
+                    submodule.path, problem
+                )));
+            }
+            eprintln!("Warning: submodule {} {}.", submodule.path, problem);
+        }
+
+        Ok(())
+    }
+
+    pub fn stash_list(&mut self) -> Result<Vec<StashInfo>, Error> {
+        let mut stashes = Vec::new();
+        self.repo.stash_foreach(|index, message, oid| {
+            stashes.push(StashInfo {
+                index,
+                message: message.to_string(),
+                oid: *oid,
+            });
+            true
+        })?;
+
+        Ok(stashes)
+    }
+
+    /// Stash the changes of the tracked files, like `git stash push`. Returns `false` if there
+    /// was nothing to stash.
+    pub fn stash_push(&mut self, message: &str) -> Result<bool, Error> {
+        let signature = self.repo.signature()?;
+
+        match self
+            .repo
+            .stash_save(&signature, message, Some(StashFlags::DEFAULT))
+        {
+            Ok(_) => Ok(true),
+            Err(err) if err.code() == ErrorCode::NotFound => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Apply and drop the last stash. The stash is kept if it could not be applied or if it has
+    /// been applied with conflicts.
+    pub fn stash_pop(&mut self) -> Result<(), Error> {
+        // NOTE: libgit2 drops the stash even when the conflicts have been written in the index
+        self.stash_apply(0, false)?;
+        if self.repo.index()?.has_conflicts() {
+            return Err(Error::new(
+                ErrorCode::Conflict,
+                ErrorClass::Stash,
+                "stash@{0} has been applied with conflicts",
+            ));
+        }
+
+        self.repo.stash_drop(0)
+    }
+
+    pub fn stash_apply(&mut self, index: usize, pop: bool) -> Result<(), Error> {
+        let res = if pop {
+            self.repo.stash_pop(index, None)
+        } else {
+            self.repo.stash_apply(index, None)
+        };
+
+        match res {
+            Err(err) if matches!(err.code(), ErrorCode::Conflict | ErrorCode::MergeConflict) => {
+                Err(Error::new(
+                    ErrorCode::Conflict,
+                    ErrorClass::Stash,
+                    format!(
+                        "stash@{{{}}} could not be applied because of conflicts: {}",
+                        index,
+                        err.message()
+                    ),
+                ))
+            }
+            res => res,
+        }
+    }
+
+    pub fn append_note(&self, rev: &str, message: &str) -> Result<Oid, Error> {
+        let oid = self.repo.revparse_single(rev)?.id();
+        let message = match self.repo.find_note(None, oid) {
+            Ok(note) => format!(
+                "{}\n{}",
+                note.message().unwrap_or_default().trim_end(),
+                message
+            ),
+            Err(err) if err.code() == ErrorCode::NotFound => message.to_string(),
+            Err(err) => return Err(err),
+        };
+
+        let signature = self.repo.signature()?;
+        self.repo
+            .note(&signature, &signature, None, oid, &message, true)
+    }
+
+    pub fn ancestors(&self, rev: &str) -> Result<Ancestors<'_>, Error> {
         let object = self.repo.revparse_single(rev)?;
         let commit = object.peel_to_commit()?;
 
@@ -319,48 +1612,225 @@ impl Git {
         })
     }
 
+    /// Rewrite the messages of the last commits, from the oldest to HEAD, keeping their trees
+    /// and authors. Returns the new HEAD.
+    pub fn reword(&mut self, messages: &[&str]) -> Result<String, Error> {
+        if messages.is_empty() {
+            return Err(Error::new(
+                ErrorCode::Invalid,
+                ErrorClass::Invalid,
+                "no commit to reword",
+            ));
+        }
+
+        let commits = self
+            .ancestors("HEAD")?
+            .take(messages.len())
+            .map(|x| (x.id(), x.parent_count()))
+            .collect::<Vec<_>>();
+        if let Some((id, _)) = commits.iter().find(|(_, parent_count)| *parent_count > 1) {
+            return Err(Error::new(
+                ErrorCode::Invalid,
+                ErrorClass::Invalid,
+                format!("cannot reword merge commit {}", id),
+            ));
+        }
+        let commits = commits.into_iter().map(|(id, _)| id).collect::<Vec<_>>();
+        if commits.len() < messages.len() {
+            return Err(Error::new(
+                ErrorCode::NotFound,
+                ErrorClass::Invalid,
+                format!("there are only {} commit(s) in the history", commits.len()),
+            ));
+        }
+
+        let oid = {
+            let signature = self.repo.signature()?;
+            let oldest = self
+                .repo
+                .find_commit(*commits.last().expect("at least one commit"))?;
+            let mut parents = oldest.parents().collect::<Vec<_>>();
+            let mut oid = None;
+            for (id, message) in commits.iter().rev().zip(messages) {
+                let commit = self.repo.find_commit(*id)?;
+
+                let new_oid = self.repo.commit(
+                    None,
+                    &commit.author(),
+                    &signature,
+                    message,
+                    &commit.tree()?,
+                    &parents.iter().collect::<Vec<_>>(),
+                )?;
+                parents = vec![self.repo.find_commit(new_oid)?];
+                oid = Some(new_oid);
+            }
+
+            oid
+        };
+        let oid = oid.expect("at least one commit is reworded");
+
+        // NOTE: the trees are unchanged, the checkout doesn't change the working directory
+        if let Some(branch_name) = self.branch_name.clone() {
+            self.repo
+                .find_branch(&branch_name, BranchType::Local)?
+                .get_mut()
+                .set_target(oid, "reword")?;
+            self.set_head(&branch_name, false)?;
+        } else {
+            self.set_head(&format!("{}", oid), true)?;
+        }
+
+        Ok(self.head_hash.clone())
+    }
+
+    /// Apply the changes of the commit `rev` on HEAD and commit them with its author and message.
+    ///
+    /// On conflict, nothing is committed: the cherry-pick is left in progress in the working
+    /// directory (like `git cherry-pick`) and the conflicting paths are returned.
+    pub fn cherry_pick(&mut self, rev: &str) -> Result<PickOutcome, Error> {
+        let (oid, message) = {
+            let commit = self.repo.revparse_single(rev)?.peel_to_commit()?;
+            if commit.parent_count() > 1 {
+                return Err(Error::new(
+                    ErrorCode::Invalid,
+                    ErrorClass::CherryPick,
+                    format!("cannot cherry-pick merge commit {}", commit.id()),
+                ));
+            }
+            let head = self.repo.head()?.peel_to_commit()?;
+
+            let mut index = self.repo.cherrypick_commit(&commit, &head, 0, None)?;
+            if index.has_conflicts() {
+                // NOTE: redo it in the working directory to let the conflicts be resolved
+                self.repo.cherrypick(&commit, None)?;
+                let mut conflicts = Vec::new();
+                for conflict in self.repo.index()?.conflicts()? {
+                    let conflict = conflict?;
+                    if let Some(entry) = conflict.our.or(conflict.their).or(conflict.ancestor) {
+                        conflicts.push(String::from_utf8_lossy(&entry.path).into_owned());
+                    }
+                }
+
+                return Ok(PickOutcome::Conflicts(conflicts));
+            }
+
+            let tree = self.repo.find_tree(index.write_tree_to(&self.repo)?)?;
+            self.repo.checkout_tree(
+                tree.as_object(),
+                Some(git2::build::CheckoutBuilder::new().safe()),
+            )?;
+
+            let message = commit.message().unwrap_or_default().to_string();
+            let oid = self.repo.commit(
+                Some("HEAD"),
+                &commit.author(),
+                &self.repo.signature()?,
+                &message,
+                &tree,
+                &[&head],
+            )?;
+
+            (oid, message)
+        };
+
+        self.head_hash = format!("{}", oid);
+        self.head_message = message;
+
+        Ok(PickOutcome::Committed(self.head_hash.clone()))
+    }
+
+    /// Commit the cherry-pick in progress once its conflicts have been resolved in the index.
+    pub fn commit_cherry_pick(&mut self) -> Result<String, Error> {
+        if self.repo.state() != RepositoryState::CherryPick {
+            return Err(Error::new(
+                ErrorCode::NotFound,
+                ErrorClass::CherryPick,
+                "there is no cherry-pick in progress",
+            ));
+        }
+
+        let (oid, message) = {
+            let commit = self
+                .repo
+                .find_reference("CHERRY_PICK_HEAD")?
+                .peel_to_commit()?;
+            let mut index = self.repo.index()?;
+            if index.has_conflicts() {
+                return Err(Error::new(
+                    ErrorCode::Conflict,
+                    ErrorClass::CherryPick,
+                    "the conflicts have not been resolved",
+                ));
+            }
+            let tree = self.repo.find_tree(index.write_tree()?)?;
+            let head = self.repo.head()?.peel_to_commit()?;
+
+            let message = commit.message().unwrap_or_default().to_string();
+            let oid = self.repo.commit(
+                Some("HEAD"),
+                &commit.author(),
+                &self.repo.signature()?,
+                &message,
+                &tree,
+                &[&head],
+            )?;
+
+            (oid, message)
+        };
+        self.repo.cleanup_state()?;
+
+        self.head_hash = format!("{}", oid);
+        self.head_message = message;
+
+        Ok(self.head_hash.clone())
+    }
+
     pub fn squash(
         &mut self,
         parent_0: &str,
         parent_1: &str,
         message: &str,
     ) -> Result<String, Error> {
-        let parent_0 = self.repo.revparse_single(parent_0)?.peel_to_commit()?;
-        let parent_1 = self.repo.revparse_single(parent_1)?.peel_to_commit()?;
-        let head = self.repo.revparse_single("HEAD")?.peel_to_commit()?;
-        let tree = self.repo.find_tree(head.tree_id())?;
+        // Make a commit with the current tree
+        let oid = {
+            let parent_0 = self.repo.revparse_single(parent_0)?.peel_to_commit()?;
+            let parent_1 = self.repo.revparse_single(parent_1)?.peel_to_commit()?;
+            let head = self.repo.revparse_single("HEAD")?.peel_to_commit()?;
+            let tree = self.repo.find_tree(head.tree_id())?;
+
+            let signature = self.repo.signature()?;
+            self.repo.commit(
+                None,
+                &signature,
+                &signature,
+                message,
+                &tree,
+                &[&parent_0, &parent_1],
+            )?
+        };
 
-        // git reset --soft to the parent "0" commit
-        if let (_, Some(mut reference)) = self
-            .repo
-            .revparse_ext(self.branch_name.as_ref().unwrap_or(&self.head_hash))?
-        {
-            reference.set_target(parent_0.id(), message)?;
+        // NOTE: the tree of the new commit is the current tree, the checkout doesn't change it
+        if let Some(branch_name) = self.branch_name.clone() {
+            self.repo
+                .find_branch(&branch_name, BranchType::Local)?
+                .get_mut()
+                .set_target(oid, message)?;
+            self.set_head(&branch_name, false)?;
         } else {
-            self.repo.set_head_detached(parent_0.id())?;
+            self.set_head(&format!("{}", oid), true)?;
         }
 
-        // Make a commit with the current tree
-        let signature = self.repo.signature()?;
-        let oid = self.repo.commit(
-            Some("HEAD"),
-            &signature,
-            &signature,
-            message,
-            &tree,
-            &[&parent_0, &parent_1],
-        )?;
-
-        self.head_hash = format!("{}", oid);
-
         Ok(self.head_hash.clone())
     }
 }
 
-fn find_git_repository() -> Result<Option<PathBuf>, Error> {
-    let mut path = current_dir().map_err(|e| Error::from_str(&e.to_string()))?;
+fn find_git_repository(path: &Path) -> Result<Option<PathBuf>, Error> {
+    let mut path = path.to_path_buf();
 
     loop {
+        // NOTE: `.git` is a file in the linked worktrees and the submodules, `Repository::open`
+        //       follows it to the actual directory
         if path.join(".git").exists() {
             return Ok(Some(path));
         }
@@ -369,7 +1839,69 @@ fn find_git_repository() -> Result<Option<PathBuf>, Error> {
         }
     }
 
-    Ok(None)
+    Ok(None)
+}
+
+fn branch_upstream(repo: &Repository, branch_name: &str) -> Result<Option<String>, Error> {
+    let branch = repo.find_branch(branch_name, BranchType::Local)?;
+
+    // NOTE: not configured or configured but gone (e.g. its remote has been removed)
+    match branch.upstream() {
+        Ok(upstream) => Ok(upstream.name()?.map(|x| x.to_string())),
+        Err(err) if err.code() == ErrorCode::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Remote of the remote-tracking branch `upstream_name`, `None` if it does not exist anymore.
+fn find_remote_name(repo: &Repository, upstream_name: &str) -> Result<Option<String>, Error> {
+    let remotes = repo.remotes()?;
+
+    // NOTE: remote names can contain slashes, the longest matching namespace is the right one
+    Ok(remotes
+        .iter()
+        .flatten()
+        .filter(|remote| upstream_name.starts_with(&format!("refs/remotes/{}/", remote)))
+        .max_by_key(|remote| remote.len())
+        .map(|x| x.to_string()))
+}
+
+/// Trailer of the merge commits made by `git try-merge` and `git sync-all`.
+pub const MERGE_TRAILER: &str = "Try-Merge: auto";
+
+/// Message of the merge commit of a single revision: the `Try-Merge: auto` trailer identifies
+/// the merge commits to squash whatever the wording of the message.
+pub fn merge_message(title: &str, revision: &str, trailer: bool) -> String {
+    let mut trailers = vec![MERGE_TRAILER.to_string()];
+    if trailer {
+        trailers.push(format!("Try-Merge-Revision: {}", revision));
+    }
+
+    append_trailers(title, trailers)
+}
+
+pub fn append_trailers(message: &str, trailers: impl IntoIterator<Item = String>) -> String {
+    let mut trailers = trailers.into_iter().peekable();
+    if trailers.peek().is_none() {
+        return message.to_string();
+    }
+
+    let mut message = format!("{}\n\n", message.trim_end());
+    for trailer in trailers {
+        message.push_str(&trailer);
+        message.push('\n');
+    }
+
+    message
+}
+
+/// Callbacks to connect to a remote with the credentials of `CredentialHandler`.
+fn remote_callbacks<'a>(config: Config) -> RemoteCallbacks<'a> {
+    let mut remote_callbacks = RemoteCallbacks::new();
+    let mut handler = CredentialHandler::new(config);
+    remote_callbacks.credentials(move |x, y, z| handler.credentials_callback(x, y, z));
+
+    remote_callbacks
 }
 
 fn get_remote_and_branch<'a>(branch: &'a Branch) -> (Option<&'a str>, &'a str) {
@@ -387,16 +1919,43 @@ fn get_remote_and_branch<'a>(branch: &'a Branch) -> (Option<&'a str>, &'a str) {
 pub struct CredentialHandler {
     second_handler: git2_credentials::CredentialHandler,
     first_attempt_failed: bool,
+    /// Private and public key of `git-tools.ssh-key` and `git-tools.ssh-public-key`.
+    ssh_key: Option<(PathBuf, PathBuf)>,
 }
 
 impl CredentialHandler {
-    pub fn new() -> CredentialHandler {
-        let git_config = git2::Config::open_default().unwrap();
+    /// `git_config` is the configuration of the repository: the keys can be configured per
+    /// repository (e.g. a deploy key).
+    pub fn new(git_config: Config) -> CredentialHandler {
+        let ssh_key = git_config
+            .get_path("git-tools.ssh-key")
+            .ok()
+            .map(|private_key| {
+                // NOTE: a public key configured at another level (e.g. globally) belongs to
+                //       another private key
+                let same_level = match (
+                    git_config.get_entry("git-tools.ssh-key"),
+                    git_config.get_entry("git-tools.ssh-public-key"),
+                ) {
+                    (Ok(private_entry), Ok(public_entry)) => {
+                        private_entry.level() == public_entry.level()
+                    }
+                    _ => false,
+                };
+                let public_key = git_config
+                    .get_path("git-tools.ssh-public-key")
+                    .ok()
+                    .filter(|_| same_level)
+                    .unwrap_or_else(|| PathBuf::from(format!("{}.pub", private_key.display())));
+
+                (private_key, public_key)
+            });
         let second_handler = git2_credentials::CredentialHandler::new(git_config);
 
         CredentialHandler {
             second_handler,
             first_attempt_failed: false,
+            ssh_key,
         }
     }
 
@@ -411,19 +1970,248 @@ impl CredentialHandler {
             let user = users::get_current_username().expect("could not get username");
             let home_dir = dirs::home_dir().expect("could not get home directory");
 
-            Cred::ssh_key(
-                username_from_url.unwrap_or_else(|| user.to_str().unwrap()),
-                Some(&home_dir.join(".ssh/id_rsa.pub")),
-                &home_dir.join(".ssh/id_rsa"),
-                None,
-            )
-        } else {
-            self.second_handler
-                .try_next_credential(url, username_from_url, allowed_types)
+            if let Some((private_key, public_key)) = self.ssh_key.as_ref() {
+                return Cred::ssh_key(
+                    username_from_url.unwrap_or_else(|| user.to_str().unwrap()),
+                    Some(public_key.as_path()).filter(|x| x.exists()),
+                    private_key,
+                    None,
+                );
+            }
+
+            // NOTE: use the key configured for the host in ~/.ssh/config if any
+            let host_config = url_host(url).and_then(|host| ssh_host_config(&home_dir, host));
+            if let Some((identity_file, config_user)) = host_config {
+                let public_key = PathBuf::from(format!("{}.pub", identity_file.display()));
+
+                return Cred::ssh_key(
+                    username_from_url
+                        .or(config_user.as_deref())
+                        .unwrap_or_else(|| user.to_str().unwrap()),
+                    Some(public_key.as_path()).filter(|x| x.exists()),
+                    &identity_file,
+                    None,
+                );
+            }
+
+            if let Some(private_key) = default_ssh_key(&home_dir) {
+                let public_key = PathBuf::from(format!("{}.pub", private_key.display()));
+
+                return Cred::ssh_key(
+                    username_from_url.unwrap_or_else(|| user.to_str().unwrap()),
+                    Some(public_key.as_path()).filter(|x| x.exists()),
+                    &private_key,
+                    None,
+                );
+            }
+        }
+
+        self.second_handler
+            .try_next_credential(url, username_from_url, allowed_types)
+    }
+}
+
+/// First default key present in ~/.ssh, in the same order as ssh.
+fn default_ssh_key(home_dir: &Path) -> Option<PathBuf> {
+    ["id_ed25519", "id_ecdsa", "id_rsa"]
+        .iter()
+        .map(|name| home_dir.join(".ssh").join(name))
+        .find(|x| x.exists())
+}
+
+/// Host, owner and name of the repository of a remote URL: scp-like syntax
+/// (git@host:owner/repo.git) or URL (https://host/owner/repo.git).
+///
+/// The owner contains all the path but the last component (e.g. GitLab's subgroups).
+pub fn normalize_remote_url(url: &str) -> Option<(String, String, String)> {
+    let url = url.trim_end_matches('/');
+    let url = url.strip_suffix(".git").unwrap_or(url);
+
+    let (host, path) = if let Some((_, rest)) = url.split_once("://") {
+        let (address, path) = rest.split_once('/')?;
+        (address.rsplit('@').next()?, path)
+    } else {
+        let (address, path) = url.split_once(':')?;
+        (address.rsplit('@').next()?, path)
+    };
+    let host = host.split(':').next()?;
+    let (owner, repo) = path.trim_start_matches('/').rsplit_once('/')?;
+
+    if host.is_empty() || owner.is_empty() || repo.is_empty() {
+        return None;
+    }
+
+    Some((host.to_string(), owner.to_string(), repo.to_string()))
+}
+
+/// Host of an URL (ssh://user@host:port/path) or of an scp-like address (user@host:path).
+fn url_host(url: &str) -> Option<&str> {
+    let address = match url.split_once("://") {
+        Some((_, rest)) => rest.split('/').next()?,
+        None => url.split(':').next()?,
+    };
+    let host = address.rsplit('@').next()?;
+    let host = host.split(':').next()?;
+
+    Some(host).filter(|x| !x.is_empty())
+}
+
+/// First `IdentityFile` (and `User`) of the `Host` sections of ~/.ssh/config matching the host.
+fn ssh_host_config(home_dir: &Path, host: &str) -> Option<(PathBuf, Option<String>)> {
+    let content = std::fs::read_to_string(home_dir.join(".ssh/config")).ok()?;
+
+    let mut matching = false;
+    let mut identity_file = None;
+    let mut user = None;
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (keyword, value) = match line.split_once(|c: char| c.is_whitespace() || c == '=') {
+            Some((keyword, value)) => (keyword, value.trim_start_matches('=').trim()),
+            None => continue,
+        };
+
+        match keyword.to_lowercase().as_str() {
+            "host" => {
+                let patterns = value.split_whitespace().collect::<Vec<_>>();
+                let is_match = |pattern: &str| {
+                    globset::Glob::new(pattern)
+                        .map(|x| x.compile_matcher().is_match(host))
+                        .unwrap_or(false)
+                };
+                matching = patterns.iter().any(|x| !x.starts_with('!') && is_match(x))
+                    && !patterns
+                        .iter()
+                        .any(|x| x.strip_prefix('!').map(is_match).unwrap_or(false));
+            }
+            // NOTE: other sections are not supported, they end the current one
+            "match" => matching = false,
+            "identityfile" if matching && identity_file.is_none() => {
+                let path = value.trim_matches('"');
+                identity_file = Some(match path.strip_prefix("~/") {
+                    Some(rest) => home_dir.join(rest),
+                    None => PathBuf::from(path),
+                });
+            }
+            "user" if matching && user.is_none() => {
+                user = Some(value.trim_matches('"').to_string());
+            }
+            _ => {}
+        }
+    }
+
+    identity_file.map(|x| (x, user))
+}
+
+pub enum UpstreamStatus {
+    /// HEAD is detached.
+    NoBranch,
+    NoUpstream,
+    UpToDate,
+    Ahead(usize),
+    Behind(usize),
+    /// Number of commits ahead and behind.
+    Diverged(usize, usize),
+}
+
+pub struct DeleteOutcome {
+    pub branch_name: String,
+    pub remote: RemoteDeletion,
+}
+
+pub enum RemoteDeletion {
+    /// The branch has no upstream or the remote has not been asked.
+    None,
+    /// The remote-tracking branch of the deleted upstream.
+    Deleted(String),
+    /// The remote-tracking branch, the branch did not exist anymore on the remote.
+    AlreadyDeleted(String),
+    /// The upstream, or the remote if the upstream doesn't resolve, whose remote does not exist
+    /// anymore.
+    RemoteGone(String),
+}
+
+pub enum PickOutcome {
+    Committed(String),
+    Conflicts(Vec<String>),
+}
+
+/// Side kept for the conflicting files that are ignored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictResolution {
+    /// The version of the merged revision (stage 3).
+    Theirs,
+    /// The version of the current branch (stage 2).
+    Ours,
+}
+
+impl std::str::FromStr for ConflictResolution {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match s {
+            "theirs" => Ok(ConflictResolution::Theirs),
+            "ours" => Ok(ConflictResolution::Ours),
+            _ => Err(Error::from_str(&format!(
+                "invalid conflict resolution `{}` (expected `theirs` or `ours`)",
+                s
+            ))),
         }
     }
 }
 
+pub struct MergeReport {
+    /// The new merge commit.
+    pub head_hash: String,
+    pub ignored_conflicts: Vec<String>,
+    /// The merged commit.
+    pub merged_oid: Oid,
+}
+
+pub struct MergeAttempt {
+    pub index: Index,
+    pub conflicts: Vec<ConflictEntry>,
+}
+
+pub struct ConflictEntry {
+    pub path: String,
+    pub ancestor: Option<IndexEntry>,
+    pub our: Option<IndexEntry>,
+    pub their: Option<IndexEntry>,
+}
+
+pub struct FileStatus {
+    pub delta: Delta,
+    pub path: String,
+    /// The path before the rename or the copy.
+    pub old_path: Option<String>,
+    pub is_staged: bool,
+}
+
+pub struct SubmoduleInfo {
+    pub name: String,
+    pub path: String,
+    pub is_dirty: bool,
+    pub is_initialized: bool,
+}
+
+pub struct StashInfo {
+    pub index: usize,
+    pub message: String,
+    pub oid: Oid,
+}
+
+pub struct ReflogEntry {
+    pub old_oid: Oid,
+    pub new_oid: Oid,
+    pub message: Option<String>,
+    pub time: Time,
+    pub committer: String,
+}
+
 pub struct Ancestors<'a> {
     current: Option<Commit<'a>>,
 }
@@ -432,9 +2220,1156 @@ impl<'a> Iterator for Ancestors<'a> {
     type Item = Commit<'a>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.current.take().map(|this| {
+        self.current.take().inspect(|this| {
             self.current = this.parent(0).ok();
-            this
         })
     }
 }
+
+/// Machine-readable output of the tools (`--json` or `GIT_TOOLS_JSON=1`): a single JSON object
+/// describing the outcome is written to the standard output and the messages are written to the
+/// standard error instead.
+pub mod output {
+    use std::fmt;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    static JSON: AtomicBool = AtomicBool::new(false);
+    static PRINTED: AtomicBool = AtomicBool::new(false);
+
+    /// Returns `true` if the JSON output is enabled by `flag` or by `GIT_TOOLS_JSON`.
+    pub fn init(flag: bool) -> bool {
+        let enabled = flag || std::env::var_os("GIT_TOOLS_JSON").is_some_and(|x| x == "1");
+        JSON.store(enabled, Ordering::Relaxed);
+
+        enabled
+    }
+
+    pub fn is_json() -> bool {
+        JSON.load(Ordering::Relaxed)
+    }
+
+    /// Print the JSON object of the outcome, only one object is printed by a command.
+    pub fn print(value: Json) {
+        println!("{}", value);
+        PRINTED.store(true, Ordering::Relaxed);
+    }
+
+    /// Print the error of the command, as a JSON object with an `error` field if enabled and if
+    /// the outcome has not been printed yet.
+    pub fn print_error(err: &dyn fmt::Display) {
+        if is_json() && !PRINTED.load(Ordering::Relaxed) {
+            print(Json::Object(vec![("error", err.to_string().into())]));
+        } else {
+            eprintln!("{}", err);
+        }
+    }
+
+    pub enum Json {
+        Null,
+        Bool(bool),
+        Number(usize),
+        String(String),
+        Array(Vec<Json>),
+        Object(Vec<(&'static str, Json)>),
+    }
+
+    impl From<bool> for Json {
+        fn from(value: bool) -> Json {
+            Json::Bool(value)
+        }
+    }
+
+    impl From<usize> for Json {
+        fn from(value: usize) -> Json {
+            Json::Number(value)
+        }
+    }
+
+    impl From<&str> for Json {
+        fn from(value: &str) -> Json {
+            Json::String(value.to_string())
+        }
+    }
+
+    impl From<String> for Json {
+        fn from(value: String) -> Json {
+            Json::String(value)
+        }
+    }
+
+    impl<T: Into<Json>> From<Option<T>> for Json {
+        fn from(value: Option<T>) -> Json {
+            value.map(Into::into).unwrap_or(Json::Null)
+        }
+    }
+
+    impl<T: Into<Json>> From<Vec<T>> for Json {
+        fn from(value: Vec<T>) -> Json {
+            Json::Array(value.into_iter().map(Into::into).collect())
+        }
+    }
+
+    impl fmt::Display for Json {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            match self {
+                Json::Null => write!(f, "null"),
+                Json::Bool(x) => write!(f, "{}", x),
+                Json::Number(x) => write!(f, "{}", x),
+                Json::String(x) => {
+                    write!(f, "\"")?;
+                    for c in x.chars() {
+                        match c {
+                            '"' => write!(f, "\\\"")?,
+                            '\\' => write!(f, "\\\\")?,
+                            '\n' => write!(f, "\\n")?,
+                            '\r' => write!(f, "\\r")?,
+                            '\t' => write!(f, "\\t")?,
+                            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+                            c => write!(f, "{}", c)?,
+                        }
+                    }
+                    write!(f, "\"")
+                }
+                Json::Array(values) => {
+                    write!(f, "[")?;
+                    for (i, value) in values.iter().enumerate() {
+                        if i > 0 {
+                            write!(f, ",")?;
+                        }
+                        write!(f, "{}", value)?;
+                    }
+                    write!(f, "]")
+                }
+                Json::Object(fields) => {
+                    write!(f, "{{")?;
+                    for (i, (key, value)) in fields.iter().enumerate() {
+                        if i > 0 {
+                            write!(f, ",")?;
+                        }
+                        write!(f, "{}:{}", Json::from(*key), value)?;
+                    }
+                    write!(f, "}}")
+                }
+            }
+        }
+    }
+}
+
+/// Print a message for the user: on the standard output, or on the standard error when the JSON
+/// output is enabled.
+#[allow(unused_macros)]
+macro_rules! say {
+    ($($arg:tt)*) => {
+        if $crate::common::output::is_json() {
+            eprintln!($($arg)*);
+        } else {
+            println!($($arg)*);
+        }
+    };
+}
+#[allow(unused_imports)]
+pub(crate) use say;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use git2::{ConfigLevel, RepositoryInitOptions};
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn init_repo(path: &Path) -> Repository {
+        let mut options = RepositoryInitOptions::new();
+        options.initial_head("main");
+        let repo = Repository::init_opts(path, &options).unwrap();
+        let mut config = repo.config().unwrap();
+        config.set_str("user.name", "Test").unwrap();
+        config.set_str("user.email", "test@example.com").unwrap();
+
+        repo
+    }
+
+    /// Write the files in the working directory and commit them on HEAD.
+    fn commit(repo: &Repository, files: &[(&str, &str)], message: &str) -> Oid {
+        let workdir = repo.workdir().unwrap();
+        let mut index = repo.index().unwrap();
+        for (path, content) in files {
+            let full_path = workdir.join(path);
+            fs::create_dir_all(full_path.parent().unwrap()).unwrap();
+            fs::write(full_path, content).unwrap();
+            index.add_path(Path::new(path)).unwrap();
+        }
+        index.write().unwrap();
+
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = repo.signature().unwrap();
+        let parents = match repo.head() {
+            Ok(head) => vec![head.peel_to_commit().unwrap()],
+            Err(_) => Vec::new(),
+        };
+        let parents = parents.iter().collect::<Vec<_>>();
+
+        repo.commit(
+            Some("HEAD"),
+            &signature,
+            &signature,
+            message,
+            &tree,
+            &parents,
+        )
+        .unwrap()
+    }
+
+    /// Repository on `main` with a first commit of `file.txt`.
+    fn scratch_repo() -> (TempDir, Git) {
+        let dir = TempDir::new().unwrap();
+        let repo = init_repo(dir.path());
+        commit(&repo, &[("file.txt", "initial\n")], "Initial commit");
+
+        (dir, Git::from_repo(repo).unwrap())
+    }
+
+    /// Commit `theirs` on the new branch `their` and `ours` on the current branch, both from HEAD.
+    fn diverge(git: &mut Git, ours: &[(&str, &str)], theirs: &[(&str, &str)]) {
+        let base = git.repo.head().unwrap().target().unwrap();
+        let their_oid = commit(&git.repo, theirs, "Their change");
+        git.repo
+            .branch("their", &git.repo.find_commit(their_oid).unwrap(), false)
+            .unwrap();
+        git.repo
+            .reset(
+                &git.repo.find_object(base, None).unwrap(),
+                git2::ResetType::Hard,
+                None,
+            )
+            .unwrap();
+        commit(&git.repo, ours, "Our change");
+        git.refresh().unwrap();
+    }
+
+    fn glob_set(globs: &[&str]) -> GlobSet {
+        let mut builder = globset::GlobSetBuilder::new();
+        for glob in globs {
+            builder.add(globset::Glob::new(glob).unwrap());
+        }
+
+        builder.build().unwrap()
+    }
+
+    /// Bare repository `<name>.git` next to the scratch repository, added as the remote `name`.
+    fn add_bare_remote(dir: &TempDir, git: &Git, name: &str) -> Repository {
+        let path = dir.path().join(format!("{}.git", name));
+        let mut options = RepositoryInitOptions::new();
+        options.bare(true).initial_head("main");
+        let remote_repo = Repository::init_opts(&path, &options).unwrap();
+        git.repo.remote(name, path.to_str().unwrap()).unwrap();
+
+        remote_repo
+    }
+
+    fn push(git: &Git, remote_name: &str, refspecs: &[&str]) {
+        git.repo
+            .find_remote(remote_name)
+            .unwrap()
+            .push(refspecs, None)
+            .unwrap();
+    }
+
+    #[test]
+    fn set_head_branch() {
+        let (dir, mut git) = scratch_repo();
+        git.branch("feature", None).unwrap();
+        commit(&git.repo, &[("file.txt", "main\n")], "Change on main");
+
+        git.set_head("feature", false).unwrap();
+
+        assert_eq!(git.repo.head().unwrap().name(), Some("refs/heads/feature"));
+        assert_eq!(git.branch_name.as_deref(), Some("feature"));
+        assert!(!git.is_detached);
+        assert_eq!(
+            git.head_hash,
+            git.get_branch_hash("feature").unwrap().unwrap()
+        );
+        assert_eq!(
+            fs::read_to_string(dir.path().join("file.txt")).unwrap(),
+            "initial\n"
+        );
+    }
+
+    #[test]
+    fn set_head_detached() {
+        let (dir, mut git) = scratch_repo();
+        let first = git.head_hash.clone();
+        commit(&git.repo, &[("file.txt", "main\n")], "Change on main");
+        git.refresh().unwrap();
+
+        git.set_head(&first, false).unwrap();
+
+        assert!(git.repo.head_detached().unwrap());
+        assert!(git.is_detached);
+        assert_eq!(git.branch_name, None);
+        assert_eq!(git.head_hash, first);
+        assert_eq!(
+            fs::read_to_string(dir.path().join("file.txt")).unwrap(),
+            "initial\n"
+        );
+
+        // NOTE: a branch is detached too when asked
+        git.set_head("main", true).unwrap();
+        assert!(git.repo.head_detached().unwrap());
+        assert_eq!(git.branch_name, None);
+        assert_eq!(git.head_hash, git.get_branch_hash("main").unwrap().unwrap());
+        assert_eq!(
+            fs::read_to_string(dir.path().join("file.txt")).unwrap(),
+            "main\n"
+        );
+    }
+
+    #[test]
+    fn is_ancestor_ahead_behind_and_equal() {
+        let (_dir, mut git) = scratch_repo();
+        let first = git.head_hash.clone();
+        let second = commit(&git.repo, &[("file.txt", "second\n")], "Second").to_string();
+
+        // NOTE: HEAD ahead of or equal to the target, the target is already merged
+        assert!(git.is_ancestor(&first, "HEAD").unwrap());
+        assert!(git.is_ancestor(&second, "HEAD").unwrap());
+
+        git.set_head(&first, true).unwrap();
+        assert!(!git.is_ancestor(&second, "HEAD").unwrap());
+        assert!(git.is_ancestor("HEAD", &second).unwrap());
+    }
+
+    #[test]
+    fn describe_tagged_history() {
+        let (_dir, git) = scratch_repo();
+        let first = git.repo.head().unwrap().peel_to_commit().unwrap();
+        git.repo
+            .tag_lightweight("v1.2.3", first.as_object(), false)
+            .unwrap();
+        commit(&git.repo, &[("file.txt", "second\n")], "Second");
+        let head = commit(&git.repo, &[("file.txt", "third\n")], "Third");
+
+        assert_eq!(git.describe(&first.id().to_string()).unwrap(), "v1.2.3");
+        assert_eq!(
+            git.describe("HEAD").unwrap(),
+            format!("v1.2.3-2-g{}", &head.to_string()[..7])
+        );
+    }
+
+    #[test]
+    fn describe_untagged_history() {
+        let (_dir, git) = scratch_repo();
+
+        let description = git.describe("HEAD").unwrap();
+
+        assert!(description.len() >= 7, "{}", description);
+        assert!(git.head_hash.starts_with(&description), "{}", description);
+    }
+
+    #[test]
+    fn upstream_status_variants() {
+        let (dir, mut git) = scratch_repo();
+        let first = git.repo.head().unwrap().target().unwrap();
+        add_bare_remote(&dir, &git, "origin");
+        git.push_set_upstream("origin", "main").unwrap();
+        assert!(matches!(
+            git.upstream_status().unwrap(),
+            UpstreamStatus::UpToDate
+        ));
+
+        let second = commit(&git.repo, &[("file.txt", "second\n")], "Second");
+        git.refresh().unwrap();
+        assert!(matches!(
+            git.upstream_status().unwrap(),
+            UpstreamStatus::Ahead(1)
+        ));
+
+        // NOTE: like a fetch of the commit pushed from somewhere else
+        git.repo
+            .reference("refs/remotes/origin/main", second, true, "test")
+            .unwrap();
+        git.repo
+            .reset(
+                &git.repo.find_object(first, None).unwrap(),
+                git2::ResetType::Hard,
+                None,
+            )
+            .unwrap();
+        git.refresh().unwrap();
+        assert!(matches!(
+            git.upstream_status().unwrap(),
+            UpstreamStatus::Behind(1)
+        ));
+
+        commit(&git.repo, &[("other.txt", "1\n")], "Other 1");
+        commit(&git.repo, &[("other.txt", "2\n")], "Other 2");
+        git.refresh().unwrap();
+        assert!(matches!(
+            git.upstream_status().unwrap(),
+            UpstreamStatus::Diverged(2, 1)
+        ));
+
+        git.branch("feature", None).unwrap();
+        git.set_head("feature", false).unwrap();
+        assert!(matches!(
+            git.upstream_status().unwrap(),
+            UpstreamStatus::NoUpstream
+        ));
+
+        git.set_head("feature", true).unwrap();
+        assert!(matches!(
+            git.upstream_status().unwrap(),
+            UpstreamStatus::NoBranch
+        ));
+    }
+
+    #[test]
+    fn reword_without_messages() {
+        let (_dir, mut git) = scratch_repo();
+        let head_hash = git.head_hash.clone();
+
+        let err = git.reword(&[]).unwrap_err();
+
+        assert_eq!(err.code(), ErrorCode::Invalid);
+        assert_eq!(git.head_hash, head_hash);
+    }
+
+    #[test]
+    fn commit_files_missing_path() {
+        let (_dir, mut git) = scratch_repo();
+        let head_hash = git.head_hash.clone();
+
+        let err = git
+            .commit_files("Add missing file", &["file.txt", "missing.txt"])
+            .unwrap_err();
+
+        assert_eq!(err.code(), ErrorCode::NotFound);
+        assert!(err.message().contains("'missing.txt'"), "{}", err);
+        assert_eq!(git.head_hash, head_hash);
+    }
+
+    #[test]
+    fn commit_files_several_files() {
+        let (dir, mut git) = scratch_repo();
+        fs::write(dir.path().join("file.txt"), "changed\n").unwrap();
+        fs::create_dir(dir.path().join("src")).unwrap();
+        fs::write(dir.path().join("src/new.rs"), "new\n").unwrap();
+
+        let (oid, tree_oid) = git
+            .commit_files("Change files", &["file.txt", "src/new.rs"])
+            .unwrap();
+
+        assert_eq!(git.head_hash, oid.to_string());
+        assert_eq!(git.repo.find_commit(oid).unwrap().tree_id(), tree_oid);
+        assert_eq!(git.blob_at("HEAD", "file.txt").unwrap(), b"changed\n");
+        assert_eq!(git.blob_at("HEAD", "src/new.rs").unwrap(), b"new\n");
+        assert!(git.blob_at("HEAD~1", "src/new.rs").is_err());
+        // NOTE: the index of the repository matches the committed tree
+        assert!(git.statuses(50).unwrap().is_empty());
+    }
+
+    #[test]
+    fn commit_tree_only_workdir_untouched() {
+        let (dir, mut git) = scratch_repo();
+        fs::write(dir.path().join("file.txt"), "changed\n").unwrap();
+        fs::write(dir.path().join("untracked.txt"), "untracked\n").unwrap();
+        let staged_oid = git
+            .repo
+            .index()
+            .unwrap()
+            .get_path(Path::new("file.txt"), 0)
+            .unwrap()
+            .id;
+
+        git.commit_tree_only("Change file", &["file.txt"]).unwrap();
+
+        assert_eq!(git.blob_at("HEAD", "file.txt").unwrap(), b"changed\n");
+        assert_eq!(
+            fs::read_to_string(dir.path().join("file.txt")).unwrap(),
+            "changed\n"
+        );
+        assert!(dir.path().join("untracked.txt").is_file());
+        // NOTE: the index still has the previous content, like a staged revert
+        let index = git.repo.index().unwrap();
+        assert_eq!(
+            index.get_path(Path::new("file.txt"), 0).unwrap().id,
+            staged_oid
+        );
+        let statuses = git.statuses(50).unwrap();
+        assert!(statuses
+            .iter()
+            .any(|x| x.path == "file.txt" && x.is_staged && x.delta == Delta::Modified));
+    }
+
+    #[test]
+    fn commit_files_from_a_subdirectory() {
+        let (dir, _git) = scratch_repo();
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        fs::write(dir.path().join("sub/file.txt"), "sub\n").unwrap();
+        let cwd = current_dir().unwrap();
+
+        let mut git = Git::discover(dir.path().join("sub")).unwrap();
+        git.commit_files("Add a file of sub", &["sub/file.txt"])
+            .unwrap();
+
+        assert_eq!(current_dir().unwrap(), cwd);
+        assert_eq!(git.blob_at("HEAD", "sub/file.txt").unwrap(), b"sub\n");
+        assert!(git.blob_at("HEAD", "file.txt").is_ok());
+        assert!(git.blob_at("HEAD", "sub/sub/file.txt").is_err());
+    }
+
+    #[test]
+    fn statuses_staged_rename() {
+        let (dir, git) = scratch_repo();
+        fs::rename(dir.path().join("file.txt"), dir.path().join("moved.txt")).unwrap();
+        let mut index = git.repo.index().unwrap();
+        index.remove_path(Path::new("file.txt")).unwrap();
+        index.add_path(Path::new("moved.txt")).unwrap();
+        index.write().unwrap();
+
+        let statuses = git.statuses(50).unwrap();
+
+        assert_eq!(statuses.len(), 1);
+        assert_eq!(statuses[0].delta, Delta::Renamed);
+        assert_eq!(statuses[0].path, "moved.txt");
+        assert_eq!(statuses[0].old_path.as_deref(), Some("file.txt"));
+        assert!(statuses[0].is_staged);
+        assert_eq!(
+            git.get_staged_and_unstaged_files().unwrap(),
+            vec!["moved.txt".to_string()]
+        );
+    }
+
+    #[test]
+    fn statuses_unstaged_rename() {
+        let (dir, git) = scratch_repo();
+        fs::rename(dir.path().join("file.txt"), dir.path().join("moved.txt")).unwrap();
+
+        let statuses = git.statuses(50).unwrap();
+
+        assert_eq!(statuses.len(), 1);
+        assert_eq!(statuses[0].delta, Delta::Renamed);
+        assert_eq!(statuses[0].path, "moved.txt");
+        assert_eq!(statuses[0].old_path.as_deref(), Some("file.txt"));
+        assert!(!statuses[0].is_staged);
+    }
+
+    #[test]
+    fn upstream_of_branch_with_upstream() {
+        let (dir, mut git) = scratch_repo();
+        add_bare_remote(&dir, &git, "origin");
+        git.push_set_upstream("origin", "main").unwrap();
+
+        assert_eq!(
+            git.upstream_of("main").unwrap(),
+            Some("origin/main".to_string())
+        );
+        assert_eq!(git.upstream, Some("origin/main".to_string()));
+    }
+
+    #[test]
+    fn upstream_of_branch_without_upstream() {
+        let (_dir, git) = scratch_repo();
+        git.branch("feature", None).unwrap();
+
+        assert_eq!(git.upstream_of("feature").unwrap(), None);
+        assert!(git.upstream_of("missing").is_err());
+    }
+
+    #[test]
+    fn delete_branch_remote_gone() {
+        let (dir, mut git) = scratch_repo();
+        add_bare_remote(&dir, &git, "origin");
+        git.branch("feature", None).unwrap();
+        git.push_set_upstream("origin", "feature").unwrap();
+        // NOTE: like a remote removed from the configuration by hand
+        let mut config = git.repo.config().unwrap();
+        config.remove_multivar("remote.origin.url", ".*").unwrap();
+        config.remove_multivar("remote.origin.fetch", ".*").unwrap();
+
+        let outcome = git.delete_branch("feature", true, None).unwrap();
+
+        assert!(matches!(outcome.remote, RemoteDeletion::RemoteGone(x) if x == "origin"));
+        assert!(git.repo.find_branch("feature", BranchType::Local).is_err());
+    }
+
+    #[test]
+    fn delete_branch_local_upstream() {
+        let (_dir, mut git) = scratch_repo();
+        git.branch("feature", None).unwrap();
+        git.repo
+            .find_branch("feature", BranchType::Local)
+            .unwrap()
+            .set_upstream(Some("main"))
+            .unwrap();
+
+        let outcome = git.delete_branch("feature", true, None).unwrap();
+
+        assert!(matches!(outcome.remote, RemoteDeletion::None));
+        assert!(git.repo.find_branch("main", BranchType::Local).is_ok());
+    }
+
+    #[test]
+    fn delete_branch_remote_already_deleted() {
+        let (dir, mut git) = scratch_repo();
+        let remote_repo = add_bare_remote(&dir, &git, "origin");
+        push(&git, "origin", &["refs/heads/main:refs/heads/main"]);
+        git.branch("feature", None).unwrap();
+        git.push_set_upstream("origin", "feature").unwrap();
+        // NOTE: like a branch deleted on the remote by someone else
+        remote_repo
+            .find_reference("refs/heads/feature")
+            .unwrap()
+            .delete()
+            .unwrap();
+
+        let outcome = git.delete_branch("feature", true, None).unwrap();
+
+        assert!(matches!(
+            outcome.remote,
+            RemoteDeletion::AlreadyDeleted(x) if x == "refs/remotes/origin/feature"
+        ));
+        assert!(git.repo.find_branch("feature", BranchType::Local).is_err());
+        assert!(git
+            .repo
+            .find_reference("refs/remotes/origin/feature")
+            .is_err());
+    }
+
+    #[test]
+    fn update_remote_head_stale_symref() {
+        let (dir, git) = scratch_repo();
+        let remote_repo = add_bare_remote(&dir, &git, "origin");
+        push(
+            &git,
+            "origin",
+            &["refs/heads/main:refs/heads/main", "HEAD:refs/heads/develop"],
+        );
+        git.repo
+            .reference_symbolic(
+                "refs/remotes/origin/HEAD",
+                "refs/remotes/origin/main",
+                true,
+                "test",
+            )
+            .unwrap();
+        remote_repo.set_head("refs/heads/develop").unwrap();
+
+        assert_eq!(
+            git.update_remote_head("origin").unwrap().as_deref(),
+            Some("refs/remotes/origin/develop")
+        );
+        assert_eq!(
+            git.repo
+                .find_reference("refs/remotes/origin/HEAD")
+                .unwrap()
+                .symbolic_target(),
+            Some("refs/remotes/origin/develop")
+        );
+        assert_eq!(git.get_default_branch("origin").unwrap(), "origin/develop");
+    }
+
+    #[test]
+    fn fetch_remote_branch_remote_ahead() {
+        let (dir, git) = scratch_repo();
+        let remote_repo = add_bare_remote(&dir, &git, "origin");
+        push(&git, "origin", &["refs/heads/main:refs/heads/main"]);
+        git.repo
+            .reference(
+                "refs/remotes/origin/main",
+                git.repo.head().unwrap().target().unwrap(),
+                true,
+                "test",
+            )
+            .unwrap();
+        // NOTE: like a commit pushed from somewhere else
+        let remote_oid = {
+            let parent = remote_repo
+                .find_reference("refs/heads/main")
+                .unwrap()
+                .peel_to_commit()
+                .unwrap();
+            let signature = git2::Signature::now("Other", "other@example.com").unwrap();
+            remote_repo
+                .commit(
+                    Some("refs/heads/main"),
+                    &signature,
+                    &signature,
+                    "Remote change",
+                    &parent.tree().unwrap(),
+                    &[&parent],
+                )
+                .unwrap()
+        };
+
+        assert!(git.fetch_remote_branch("origin/main").unwrap());
+        git.branch("forked", Some("origin/main")).unwrap();
+
+        assert_eq!(
+            git.get_branch_hash("forked").unwrap(),
+            Some(remote_oid.to_string())
+        );
+        // NOTE: the local revisions are not fetched
+        assert!(!git.fetch_remote_branch("main").unwrap());
+        assert!(!git.fetch_remote_branch(&git.head_hash).unwrap());
+    }
+
+    #[test]
+    fn find_remote_name_longest_namespace() {
+        let (_dir, git) = scratch_repo();
+        for name in &["feature", "team", "team/feature"] {
+            git.repo.remote(name, "/nonexistent").unwrap();
+        }
+
+        let remote_of = |upstream_name: &str| find_remote_name(&git.repo, upstream_name).unwrap();
+        assert_eq!(
+            remote_of("refs/remotes/team/feature/feature").as_deref(),
+            Some("team/feature")
+        );
+        assert_eq!(
+            remote_of("refs/remotes/team/feature2").as_deref(),
+            Some("team")
+        );
+        assert_eq!(
+            remote_of("refs/remotes/feature/team").as_deref(),
+            Some("feature")
+        );
+        assert_eq!(remote_of("refs/remotes/featured/x"), None);
+    }
+
+    #[test]
+    fn delete_branch_named_like_its_remote() {
+        let (dir, mut git) = scratch_repo();
+        let remote_repo = add_bare_remote(&dir, &git, "my-fork");
+        push(&git, "my-fork", &["refs/heads/main:refs/heads/main"]);
+        git.branch("fork", None).unwrap();
+        git.push_set_upstream("my-fork", "fork").unwrap();
+
+        let outcome = git.delete_branch("fork", true, None).unwrap();
+
+        assert!(matches!(
+            outcome.remote,
+            RemoteDeletion::Deleted(x) if x == "refs/remotes/my-fork/fork"
+        ));
+        assert!(remote_repo.find_reference("refs/heads/fork").is_err());
+        assert!(remote_repo.find_reference("refs/heads/main").is_ok());
+    }
+
+    #[test]
+    fn delete_remote_branch_untracked_default_branch() {
+        let (dir, git) = scratch_repo();
+        let remote_repo = add_bare_remote(&dir, &git, "fork");
+        push(&git, "fork", &["refs/heads/main:refs/heads/main"]);
+
+        let err = git.delete_remote_branch("fork", "main").unwrap_err();
+        assert!(err.message().contains("default branch"), "{}", err);
+        assert!(remote_repo.find_reference("refs/heads/main").is_ok());
+    }
+
+    #[test]
+    fn delete_remote_branch_untracked() {
+        let (dir, git) = scratch_repo();
+        let remote_repo = add_bare_remote(&dir, &git, "fork");
+        push(
+            &git,
+            "fork",
+            &["refs/heads/main:refs/heads/main", "HEAD:refs/heads/feature"],
+        );
+
+        assert!(git.delete_remote_branch("fork", "feature").unwrap());
+        assert!(remote_repo.find_reference("refs/heads/feature").is_err());
+        assert!(git
+            .repo
+            .find_reference("refs/remotes/fork/feature")
+            .is_err());
+    }
+
+    #[test]
+    fn try_merge_index_sides_of_the_conflicts() {
+        let (dir, mut git) = scratch_repo();
+        commit(&git.repo, &[("deleted.txt", "base\n")], "Base");
+        diverge(
+            &mut git,
+            &[("file.txt", "ours\n"), ("other.txt", "ours\n")],
+            &[("file.txt", "theirs\n"), ("deleted.txt", "theirs\n")],
+        );
+        fs::remove_file(dir.path().join("deleted.txt")).unwrap();
+        let mut index = git.repo.index().unwrap();
+        index.remove_path(Path::new("deleted.txt")).unwrap();
+        index.write().unwrap();
+        commit(&git.repo, &[], "Delete");
+        git.refresh().unwrap();
+
+        let attempt = git.try_merge_index("HEAD", "their").unwrap();
+
+        let conflicts = attempt
+            .conflicts
+            .iter()
+            .map(|x| {
+                (
+                    x.path.as_str(),
+                    x.ancestor.is_some(),
+                    x.our.is_some(),
+                    x.their.is_some(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            conflicts,
+            vec![
+                ("deleted.txt", true, false, true),
+                ("file.txt", true, true, true)
+            ]
+        );
+        assert!(attempt.index.has_conflicts());
+        assert!(attempt.index.get_path(Path::new("other.txt"), 0).is_some());
+        assert_eq!(
+            git.list_conflicts("HEAD", "their").unwrap(),
+            vec!["deleted.txt".to_string(), "file.txt".to_string()]
+        );
+    }
+
+    #[test]
+    fn check_no_conflict_ignorable_and_blocking() {
+        let (_dir, mut git) = scratch_repo();
+        commit(
+            &git.repo,
+            &[("Cargo.lock", "base\n"), ("src/main.rs", "base\n")],
+            "Base",
+        );
+        diverge(
+            &mut git,
+            &[("Cargo.lock", "ours\n"), ("src/main.rs", "ours\n")],
+            &[("Cargo.lock", "theirs\n"), ("src/main.rs", "theirs\n")],
+        );
+
+        let lock_only = glob_set(&["*.lock"]);
+        let both = glob_set(&["*.lock", "*.rs"]);
+        let theirs = ConflictResolution::Theirs;
+        assert_eq!(
+            git.check_no_conflict("their", &lock_only, theirs, &[])
+                .unwrap(),
+            None
+        );
+        assert_eq!(
+            git.check_no_conflict("their", &both, theirs, &[]).unwrap(),
+            Some(vec!["Cargo.lock".to_string(), "src/main.rs".to_string()])
+        );
+
+        // NOTE: the files to regenerate are ignorable too
+        let regenerate = vec![(
+            globset::Glob::new("*.rs").unwrap().compile_matcher(),
+            "true".to_string(),
+        )];
+        assert_eq!(
+            git.check_no_conflict("their", &lock_only, theirs, &regenerate)
+                .unwrap(),
+            Some(vec!["Cargo.lock".to_string(), "src/main.rs".to_string()])
+        );
+    }
+
+    #[test]
+    fn merge_ignore_whitespace_divergence() {
+        let (_dir, mut git) = scratch_repo();
+        commit(
+            &git.repo,
+            &[("file.txt", "fn main() {\n    run();\n}\n")],
+            "Base",
+        );
+        diverge(
+            &mut git,
+            &[("file.txt", "fn main() {\n    run();  \n}\n")],
+            &[("file.txt", "fn main()  {\n\trun();\n}\n")],
+        );
+
+        assert_eq!(
+            git.list_conflicts("HEAD", "their").unwrap(),
+            vec!["file.txt".to_string()]
+        );
+        let report = git
+            .merge_no_conflict(
+                "their",
+                "Merge",
+                &glob_set(&[]),
+                ConflictResolution::Theirs,
+                &[],
+                false,
+                false,
+                false,
+            )
+            .unwrap();
+        assert!(report.is_none());
+
+        git.merge_ignore_whitespace = true;
+        assert!(git.list_conflicts("HEAD", "their").unwrap().is_empty());
+        let report = git
+            .merge_no_conflict(
+                "their",
+                "Merge",
+                &glob_set(&[]),
+                ConflictResolution::Theirs,
+                &[],
+                false,
+                false,
+                false,
+            )
+            .unwrap()
+            .unwrap();
+        assert!(report.ignored_conflicts.is_empty());
+        assert_eq!(
+            git.repo
+                .head()
+                .unwrap()
+                .peel_to_commit()
+                .unwrap()
+                .parent_count(),
+            2
+        );
+    }
+
+    /// Merge `their` ignoring the conflicts of `globs` with `resolution`.
+    fn merge_ignoring(
+        git: &mut Git,
+        globs: &[&str],
+        resolution: ConflictResolution,
+    ) -> MergeReport {
+        git.merge_no_conflict(
+            "their",
+            "Merge",
+            &glob_set(globs),
+            resolution,
+            &[],
+            false,
+            false,
+            false,
+        )
+        .unwrap()
+        .expect("the conflicts are ignored")
+    }
+
+    #[test]
+    fn merge_no_conflict_ignored_lock_files() {
+        let (_dir, mut git) = scratch_repo();
+        commit(
+            &git.repo,
+            &[
+                ("package-lock.json", "base\n"),
+                ("frontend/yarn.lock", "base\n"),
+            ],
+            "Base",
+        );
+        diverge(
+            &mut git,
+            &[
+                ("package-lock.json", "ours\n"),
+                ("frontend/yarn.lock", "ours\n"),
+            ],
+            &[
+                ("package-lock.json", "theirs\n"),
+                ("frontend/yarn.lock", "theirs\n"),
+            ],
+        );
+
+        let report = merge_ignoring(
+            &mut git,
+            &["package-lock.json", "*.lock"],
+            ConflictResolution::Theirs,
+        );
+
+        assert_eq!(
+            report.ignored_conflicts,
+            vec![
+                "frontend/yarn.lock".to_string(),
+                "package-lock.json".to_string()
+            ]
+        );
+        assert_eq!(
+            git.blob_at("HEAD", "package-lock.json").unwrap(),
+            b"theirs\n"
+        );
+        assert_eq!(
+            git.blob_at("HEAD", "frontend/yarn.lock").unwrap(),
+            b"theirs\n"
+        );
+        let index = git.repo.index().unwrap();
+        assert!(!index.has_conflicts());
+        assert!(index.iter().all(|x| (x.flags >> 12) & 0x3 == 0));
+    }
+
+    #[test]
+    fn merge_no_conflict_resolution_ours_and_theirs() {
+        for (resolution, expected) in &[
+            (ConflictResolution::Ours, "ours\n"),
+            (ConflictResolution::Theirs, "theirs\n"),
+        ] {
+            let (_dir, mut git) = scratch_repo();
+            commit(&git.repo, &[("Cargo.lock", "base\n")], "Base");
+            diverge(
+                &mut git,
+                &[("Cargo.lock", "ours\n")],
+                &[("Cargo.lock", "theirs\n")],
+            );
+
+            merge_ignoring(&mut git, &["Cargo.lock"], *resolution);
+
+            assert_eq!(
+                git.blob_at("HEAD", "Cargo.lock").unwrap(),
+                expected.as_bytes()
+            );
+            let index = git.repo.index().unwrap();
+            assert!(!index.has_conflicts());
+            let entry = index.get_path(Path::new("Cargo.lock"), 0).unwrap();
+            assert_eq!(
+                git.repo.find_blob(entry.id).unwrap().content(),
+                expected.as_bytes()
+            );
+        }
+    }
+
+    #[test]
+    fn merge_no_conflict_annotated_tag() {
+        let (_dir, mut git) = scratch_repo();
+        diverge(&mut git, &[("a.txt", "ours\n")], &[("b.txt", "theirs\n")]);
+        let their_oid = {
+            let their = git.repo.revparse_single("their").unwrap();
+            let signature = git.repo.signature().unwrap();
+            git.repo
+                .tag("v1.0.0", &their, &signature, "Release", false)
+                .unwrap();
+
+            their.id()
+        };
+
+        let report = git
+            .merge_no_conflict(
+                "v1.0.0",
+                "Merge v1.0.0",
+                &glob_set(&[]),
+                ConflictResolution::Theirs,
+                &[],
+                false,
+                false,
+                false,
+            )
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(report.merged_oid, their_oid);
+        assert_eq!(git.blob_at("HEAD", "b.txt").unwrap(), b"theirs\n");
+    }
+
+    #[test]
+    fn check_no_conflict_without_conflict() {
+        let (_dir, mut git) = scratch_repo();
+        diverge(&mut git, &[("a.txt", "ours\n")], &[("b.txt", "theirs\n")]);
+
+        assert_eq!(
+            git.check_no_conflict("their", &glob_set(&[]), ConflictResolution::Theirs, &[])
+                .unwrap(),
+            Some(Vec::new())
+        );
+    }
+
+    #[test]
+    fn normalize_remote_url_ssh_and_https() {
+        let expected = Some((
+            "github.com".to_string(),
+            "owner".to_string(),
+            "repo".to_string(),
+        ));
+
+        for url in &[
+            "git@github.com:owner/repo.git",
+            "git@github.com:owner/repo",
+            "ssh://git@github.com/owner/repo.git",
+            "ssh://git@github.com:22/owner/repo",
+            "https://github.com/owner/repo.git",
+            "https://github.com/owner/repo",
+            "https://user@github.com/owner/repo/",
+        ] {
+            assert_eq!(normalize_remote_url(url), expected, "{}", url);
+        }
+    }
+
+    #[test]
+    fn normalize_remote_url_invalid() {
+        for url in &[
+            "",
+            "/local/path",
+            "https://github.com/repo.git",
+            "git@github.com:repo",
+        ] {
+            assert_eq!(normalize_remote_url(url), None, "{}", url);
+        }
+    }
+
+    #[test]
+    fn default_ssh_key_ed25519() {
+        let home_dir = TempDir::new().unwrap();
+        fs::create_dir(home_dir.path().join(".ssh")).unwrap();
+        fs::write(home_dir.path().join(".ssh/id_ed25519"), "private").unwrap();
+        fs::write(home_dir.path().join(".ssh/id_ed25519.pub"), "public").unwrap();
+
+        assert_eq!(
+            default_ssh_key(home_dir.path()),
+            Some(home_dir.path().join(".ssh/id_ed25519"))
+        );
+    }
+
+    #[test]
+    fn default_ssh_key_missing() {
+        let home_dir = TempDir::new().unwrap();
+
+        assert_eq!(default_ssh_key(home_dir.path()), None);
+    }
+
+    #[test]
+    fn ssh_key_of_the_repository_config() {
+        let (_dir, git) = scratch_repo();
+        git.repo
+            .config()
+            .unwrap()
+            .set_str("git-tools.ssh-key", "/keys/deploy")
+            .unwrap();
+
+        let handler = CredentialHandler::new(git.repo.config().unwrap());
+
+        assert_eq!(
+            handler.ssh_key,
+            Some((
+                PathBuf::from("/keys/deploy"),
+                PathBuf::from("/keys/deploy.pub")
+            ))
+        );
+    }
+
+    #[test]
+    fn ssh_key_of_the_repository_overrides_the_global_one() {
+        let dir = TempDir::new().unwrap();
+        let global_path = dir.path().join("global");
+        let local_path = dir.path().join("local");
+        fs::write(
+            &global_path,
+            "[git-tools]\n\tssh-key = /keys/global\n\tssh-public-key = /keys/global.pub\n",
+        )
+        .unwrap();
+        fs::write(&local_path, "[git-tools]\n\tssh-key = /keys/local\n").unwrap();
+        let mut config = Config::new().unwrap();
+        config
+            .add_file(&global_path, ConfigLevel::Global, false)
+            .unwrap();
+        config
+            .add_file(&local_path, ConfigLevel::Local, false)
+            .unwrap();
+
+        let handler = CredentialHandler::new(config);
+
+        assert_eq!(
+            handler.ssh_key,
+            Some((
+                PathBuf::from("/keys/local"),
+                PathBuf::from("/keys/local.pub")
+            ))
+        );
+    }
+}
@@ -0,0 +1,123 @@
+mod common;
+
+use common::output::{self, Json};
+use common::Git;
+
+use anyhow::{bail, Context, Result};
+use git2::Delta;
+use std::env;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+#[structopt(
+    bin_name = "git changed",
+    about = env!("CARGO_PKG_DESCRIPTION")
+)]
+pub struct Changed {
+    /// Run as if the command was started in <path> instead of the current working directory.
+    #[structopt(short = "C", long = "repo", value_name = "path", default_value = ".")]
+    repo: PathBuf,
+
+    /// Only show the paths of the files. (This is the default.)
+    #[structopt(long, conflicts_with = "status")]
+    name_only: bool,
+
+    /// Show the status of the files (A, M, D, R, ...) before their path.
+    #[structopt(long)]
+    status: bool,
+
+    /// Print the files as a JSON array. (Can also be enabled with `GIT_TOOLS_JSON=1`.)
+    #[structopt(long)]
+    json: bool,
+
+    /// Revision to compare to (default branch or origin/main by default).
+    revision: Option<String>,
+}
+
+fn main() {
+    let exit_status = execute();
+    std::process::exit(exit_status);
+}
+
+const SUCCESS: i32 = 0;
+const FAILURE: i32 = 1;
+
+fn execute() -> i32 {
+    let opts = Changed::from_args();
+    output::init(opts.json);
+
+    if let Err(err) = run(opts) {
+        output::print_error(&err);
+
+        FAILURE
+    } else {
+        SUCCESS
+    }
+}
+
+pub fn run(params: Changed) -> Result<()> {
+    let git = Git::open_light(&params.repo).context("Could not open repository")?;
+
+    let revision = match params.revision.clone() {
+        Some(x) => x,
+        None => git.get_default_branch("origin")?,
+    };
+    let merge_base = match git
+        .merge_base("HEAD", &revision)
+        .with_context(|| format!("Could not find revision: {}", revision))?
+    {
+        Some(x) => x,
+        None => bail!("No common history with {}", revision),
+    };
+
+    let changes = git
+        .diff_name_status(&merge_base, "HEAD")
+        .context("Could not compute the changes")?;
+
+    let json = output::is_json();
+    let show_status = params.status && !params.name_only;
+    let mut entries = Vec::new();
+    for (delta, path) in changes {
+        let status = status_letter(delta);
+        if show_status {
+            if json {
+                entries.push(Json::Object(vec![
+                    ("status", status.into()),
+                    ("path", path.into()),
+                ]));
+            } else {
+                println!("{}\t{}", status, path);
+            }
+        } else {
+            // NOTE: only the new path of the renamed and copied files
+            let path = path.rsplit(" -> ").next().unwrap_or_default();
+            if json {
+                entries.push(path.into());
+            } else {
+                println!("{}", path);
+            }
+        }
+    }
+
+    if json {
+        output::print(Json::Array(entries));
+    }
+
+    Ok(())
+}
+
+fn status_letter(delta: Delta) -> &'static str {
+    match delta {
+        Delta::Added => "A",
+        Delta::Deleted => "D",
+        Delta::Modified => "M",
+        Delta::Renamed => "R",
+        Delta::Copied => "C",
+        Delta::Typechange => "T",
+        Delta::Untracked => "?",
+        Delta::Ignored => "!",
+        Delta::Conflicted => "U",
+        Delta::Unmodified | Delta::Unreadable => " ",
+    }
+}
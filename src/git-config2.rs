@@ -0,0 +1,312 @@
+mod common;
+
+use common::Git;
+
+use anyhow::{bail, Context, Result};
+use git2::ConfigLevel;
+use std::env;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+#[structopt(
+    bin_name = "git config2",
+    about = env!("CARGO_PKG_DESCRIPTION")
+)]
+pub struct Config2 {
+    /// Run as if the command was started in <path> instead of the current working directory.
+    #[structopt(short = "C", long = "repo", value_name = "path", default_value = ".")]
+    repo: PathBuf,
+
+    /// Write to the global configuration instead of the repository's configuration.
+    #[structopt(long)]
+    global: bool,
+
+    /// Add the value to a multi-valued key instead of replacing all its values.
+    #[structopt(long)]
+    add: bool,
+
+    /// Configuration key to show or set. (All the keys are listed if not provided.)
+    key: Option<String>,
+
+    /// New value for the configuration key.
+    value: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Kind {
+    Bool,
+    Int,
+    Glob,
+    String,
+    Choice(&'static [&'static str]),
+}
+
+struct Key {
+    /// A name ending with `*` is the prefix of several keys (e.g. `try-merge.regenerate.*`).
+    name: &'static str,
+    kind: Kind,
+    multi: bool,
+    description: &'static str,
+}
+
+impl Key {
+    fn prefix(&self) -> Option<&'static str> {
+        self.name.strip_suffix('*')
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        match self.prefix() {
+            Some(prefix) => name.len() > prefix.len() && name.starts_with(prefix),
+            None => self.name == name,
+        }
+    }
+}
+
+const KEYS: &[Key] = &[
+    Key {
+        name: "try-merge.squash",
+        kind: Kind::Bool,
+        multi: false,
+        description: "Squash all the merge commits together at the end.",
+    },
+    Key {
+        name: "try-merge.ignore-conflict",
+        kind: Kind::Glob,
+        multi: true,
+        description: "Files that can be taken from the upstream when they are conflicting.",
+    },
+    Key {
+        name: "try-merge.conflict-resolution",
+        kind: Kind::Choice(&["theirs", "ours"]),
+        multi: false,
+        description: "Side kept for the ignored conflicts: theirs (the upstream) or ours.",
+    },
+    Key {
+        name: "try-merge.no-squash-branches",
+        kind: Kind::Glob,
+        multi: true,
+        description: "Branches that are never squashed, even if squashing is requested.",
+    },
+    Key {
+        name: "try-merge.first-parent-upstream",
+        kind: Kind::Bool,
+        multi: false,
+        description: "Make the upstream the first parent of the merge commits.",
+    },
+    Key {
+        name: "try-merge.auto-resolve-commits",
+        kind: Kind::String,
+        multi: true,
+        description: "Commits (hash prefixes or message globs) whose conflicts take the upstream.",
+    },
+    // NOTE: git lowercases the part of the key after the last dot, `Cargo.lock` is stored as the
+    //       subsection `regenerate.Cargo` and the key `lock`
+    Key {
+        name: "try-merge.regenerate.*",
+        kind: Kind::String,
+        multi: false,
+        description:
+            "Command regenerating the conflicting files matching the glob after `regenerate.` \
+             (its extension is lowercased by git).",
+    },
+    Key {
+        name: "fork.pr-command",
+        kind: Kind::String,
+        multi: false,
+        description: "Command run by `git fork --pr` to open a pull request (the branch is `$1`).",
+    },
+    Key {
+        name: "push2.confirm-threshold",
+        kind: Kind::Int,
+        multi: false,
+        description: "Ask for confirmation when `git push2` publishes more commits than this.",
+    },
+    Key {
+        name: "push2.confirm-non-interactive",
+        kind: Kind::Bool,
+        multi: false,
+        description: "Require --yes instead of asking for confirmation without a terminal.",
+    },
+    Key {
+        name: "git-tools.ssh-key",
+        kind: Kind::String,
+        multi: false,
+        description: "Private SSH key tried first to connect to the remotes.",
+    },
+    Key {
+        name: "git-tools.ssh-public-key",
+        kind: Kind::String,
+        multi: false,
+        description: "Public SSH key of `git-tools.ssh-key` (its path with `.pub` by default).",
+    },
+];
+
+fn main() {
+    let exit_status = execute();
+    std::process::exit(exit_status);
+}
+
+const SUCCESS: i32 = 0;
+const FAILURE: i32 = 1;
+
+fn execute() -> i32 {
+    let opts = Config2::from_args();
+
+    if let Err(err) = run(opts) {
+        eprintln!("{}", err);
+
+        FAILURE
+    } else {
+        SUCCESS
+    }
+}
+
+pub fn run(params: Config2) -> Result<()> {
+    let git = Git::open_at(&params.repo).context("Could not open repository")?;
+
+    let name = match params.key.as_deref() {
+        Some(x) => x,
+        None => {
+            for key in KEYS {
+                print_key(&git, key, key.name)?;
+            }
+            return Ok(());
+        }
+    };
+
+    let key = match KEYS.iter().find(|x| x.matches(name)) {
+        Some(x) => x,
+        None => bail!(
+            "Unknown key `{}`, the known keys are: {}",
+            name,
+            KEYS.iter().map(|x| x.name).collect::<Vec<_>>().join(", ")
+        ),
+    };
+
+    let value = match params.value.as_deref() {
+        Some(x) => x,
+        None => {
+            println!("# {}", key.description);
+            return print_key(&git, key, name);
+        }
+    };
+
+    if let Some(prefix) = key.prefix() {
+        globset::Glob::new(&name[prefix.len()..])
+            .with_context(|| format!("Invalid glob in the key `{}`", name))?;
+    }
+    validate(key, name, value)?;
+
+    if params.add && !key.multi {
+        bail!("Key `{}` cannot have multiple values", name);
+    }
+
+    let level = if params.global {
+        ConfigLevel::Global
+    } else {
+        ConfigLevel::Local
+    };
+    let mut config = git
+        .repo
+        .config()
+        .and_then(|x| x.open_level(level))
+        .context("Could not open configuration")?;
+
+    if key.multi {
+        if !params.add {
+            match config.remove_multivar(name, ".*") {
+                Err(err) if err.code() != git2::ErrorCode::NotFound => return Err(err.into()),
+                _ => {}
+            }
+        }
+        // NOTE: a regular expression that never matches appends the value
+        config.set_multivar(name, "$^", value)?;
+    } else {
+        config.set_str(name, value)?;
+    }
+    println!("{} = {}", name, value);
+
+    Ok(())
+}
+
+fn validate(key: &Key, name: &str, value: &str) -> Result<()> {
+    match key.kind {
+        Kind::Bool => {
+            git2::Config::parse_bool(value)
+                .with_context(|| format!("Invalid boolean for `{}`: {}", name, value))?;
+        }
+        Kind::Int => {
+            git2::Config::parse_i64(value)
+                .with_context(|| format!("Invalid integer for `{}`: {}", name, value))?;
+        }
+        Kind::Glob => {
+            globset::Glob::new(value)
+                .with_context(|| format!("Invalid glob for `{}`: {}", name, value))?;
+        }
+        Kind::String => {}
+        Kind::Choice(choices) => {
+            if !choices.contains(&value) {
+                bail!(
+                    "Invalid value for `{}`: {} (expected one of: {})",
+                    name,
+                    value,
+                    choices.join(", ")
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// `name` is the key itself for the keys that are a prefix: all the keys with this prefix are
+/// printed.
+fn print_key(git: &Git, key: &Key, name: &str) -> Result<()> {
+    if let Some(prefix) = key.prefix().filter(|_| name == key.name) {
+        let mut found = false;
+        for entry in git
+            .config
+            .entries(Some(&format!("^{}", prefix.replace('.', "\\."))))
+            .iter()
+            .flatten()
+            .filter_map(|x| x.ok())
+        {
+            found = true;
+            println!(
+                "{} = {}",
+                entry.name().expect("invalid UTF-8"),
+                entry.value().expect("invalid UTF-8")
+            );
+        }
+        if !found {
+            println!("{} (unset)", key.name);
+        }
+
+        return Ok(());
+    }
+
+    let mut values = Vec::new();
+    for entry in git
+        .config
+        .multivar(name, None)
+        .iter()
+        .flatten()
+        .filter_map(|x| x.ok())
+    {
+        values.push(entry.value().expect("invalid UTF-8").to_string());
+    }
+
+    if values.is_empty() {
+        println!("{} (unset)", name);
+    } else if key.multi {
+        for value in values {
+            println!("{} = {}", name, value);
+        }
+    } else {
+        // NOTE: the last value is the effective one
+        println!("{} = {}", name, values.last().unwrap());
+    }
+
+    Ok(())
+}
@@ -1,8 +1,13 @@
 mod common;
 
-use anyhow::{bail, Context, Result};
+use common::output::{self, Json};
+use common::{say, Git, RemoteDeletion};
+
+use anyhow::{anyhow, bail, Context, Result};
 use std::env;
-use std::io::Write;
+use std::io::BufRead;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use structopt::StructOpt;
 
 #[derive(StructOpt, Debug)]
@@ -11,7 +16,59 @@ use structopt::StructOpt;
     about = env!("CARGO_PKG_DESCRIPTION")
 )]
 pub struct Delete {
-    branch_name: String,
+    /// Run as if the command was started in <path> instead of the current working directory.
+    #[structopt(short = "C", long = "repo", value_name = "path", default_value = ".")]
+    repo: PathBuf,
+
+    /// Local branches to delete, each one is deleted independently of the others.
+    #[structopt(
+        value_name = "branch",
+        required_unless_one = &["prune", "stdin"],
+        conflicts_with = "stdin"
+    )]
+    branch_names: Vec<String>,
+
+    /// Read the names of the branches to delete from the standard input, one per line (e.g. the
+    /// output of `git branch --merged`).
+    #[structopt(long)]
+    stdin: bool,
+
+    /// Delete the remote-tracking branches of <remote> (origin by default) that no longer exist
+    /// on the remote. (The remote must be given as `--prune=<remote>`.)
+    #[structopt(long, value_name = "remote", require_equals = true)]
+    prune: Option<Option<String>>,
+
+    /// Delete the branch on this remote instead of the upstream, even if it is not tracked.
+    #[structopt(long, value_name = "name")]
+    remote: Option<String>,
+
+    /// Only delete the local branch: the upstream is left untouched.
+    #[structopt(long, conflicts_with = "remote")]
+    no_remote: bool,
+
+    /// Abort if commits of the branch would not be reachable from any other branch or tag.
+    #[structopt(long)]
+    keep_commits: bool,
+
+    /// Delete the branch even if commits would become unreachable. (See --keep-commits.)
+    #[structopt(long, short = "f")]
+    force: bool,
+
+    /// Only delete the branch if its last commit is older than this duration (e.g. 30d, 6months).
+    /// The more recent branches are skipped, which doesn't make the command fail.
+    #[structopt(long, parse(try_from_str = humantime::parse_duration))]
+    older_than: Option<Duration>,
+
+    /// Only show the branches that would be deleted, after the checks of --older-than and
+    /// --keep-commits: nothing is deleted. (E.g. `git branch --merged | git delete --stdin
+    /// --dry-run`.)
+    #[structopt(long, conflicts_with = "prune")]
+    dry_run: bool,
+
+    /// Print the deleted branches as a JSON object. (Can also be enabled with
+    /// `GIT_TOOLS_JSON=1`.)
+    #[structopt(long)]
+    json: bool,
 }
 
 fn main() {
@@ -24,9 +81,10 @@ const FAILURE: i32 = 1;
 
 fn execute() -> i32 {
     let opts = Delete::from_args();
+    output::init(opts.json);
 
     if let Err(err) = run(opts) {
-        eprintln!("{}", err);
+        output::print_error(&err);
 
         FAILURE
     } else {
@@ -35,73 +93,302 @@ fn execute() -> i32 {
 }
 
 pub fn run(params: Delete) -> Result<()> {
-    let branch_name = params.branch_name.as_str();
-    let repo = git2::Repository::open(".").context("Could not open repository")?;
-
-    let mut branch = repo
-        .find_branch(&params.branch_name, git2::BranchType::Local)
-        .with_context(|| format!("Could not find local branch: {}", params.branch_name))?;
-    let branch_name = branch
-        .name()
-        .context("Could not retrieve branch name")?
-        .expect("not valid utf-8")
-        .to_owned();
-
-    if branch.is_head() {
-        bail!("Aborted: cannot delete branch currently pointed at by HEAD");
-    }
-
-    // delete remote branch if any
-    if let Ok(upstream) = branch.upstream() {
-        let upstream_name = upstream.get().name().expect("not valid utf-8");
-        let remote_name = upstream_name
-            .strip_suffix(&branch_name)
-            .and_then(|x| x.strip_prefix("refs/remotes/"))
-            .context("Could not find remote name")?
-            .trim_end_matches('/');
-
-        let mut remote = repo
-            .find_remote(remote_name)
+    let mut git = Git::open_at(&params.repo).context("Could not open repository")?;
+    if git.is_detached {
+        eprintln!("HEAD is detached: no branch is protected as the current branch.");
+    }
+    let repo = &git.repo;
+
+    let mut pruned = Vec::new();
+    if let Some(remote_name) = params.prune.as_ref() {
+        let remote_name = remote_name.as_deref().unwrap_or("origin");
+        repo.find_remote(remote_name)
             .with_context(|| format!("Could not find remote `{}`", remote_name))?;
 
-        // this is a reference to the default branch if it exists
-        let head_reference = repo.find_reference(&format!("refs/remotes/{}/HEAD", remote_name));
-
-        let default_branch_name = {
-            match head_reference.as_ref() {
-                Ok(reference) => reference
-                    .symbolic_target()
-                    .context("Invalid reference HEAD: not symbolic reference")?
-                    .to_string(),
-                Err(err) if err.code() == git2::ErrorCode::NotFound => {
-                    format!("refs/remote/{}/master", remote_name)
-                }
-                Err(err) => bail!("Could not find default branch for this repository: {}", err),
-            }
-        };
+        pruned = git
+            .prune_remote(remote_name)
+            .with_context(|| format!("Could not prune remote `{}`", remote_name))?;
+        for name in pruned.iter() {
+            say!("Stale remote-tracking branch deleted: {}", name);
+        }
+    }
 
-        if upstream_name == default_branch_name {
-            bail!("Aborted: deleting default branch is forbidden");
+    let branch_names = if params.stdin {
+        let mut names = Vec::new();
+        for line in std::io::stdin().lock().lines() {
+            let line = line.context("Could not read the standard input")?;
+            // NOTE: `git branch` marks the current branch with `*` and the other worktrees with `+`
+            let name = line.trim().trim_start_matches(&['*', '+'][..]).trim_start();
+            if !name.is_empty() {
+                names.push(name.to_string());
+            }
         }
 
-        // TODO better handling for credentials using git2_credentials
-        //      make sure it works with ~/.ssh/id_rsa and ssh-agent
-        let mut remote_callbacks = git2::RemoteCallbacks::new();
-        let mut handler = common::CredentialHandler::new();
-        remote_callbacks.credentials(move |x, y, z| handler.credentials_callback(x, y, z));
+        names
+    } else {
+        params.branch_names.clone()
+    };
 
-        let mut push_options = git2::PushOptions::new();
-        push_options.remote_callbacks(remote_callbacks);
+    // NOTE: a single branch is reported as before: its error is the error of the command
+    if let [branch_name] = branch_names.as_slice() {
+        if !params.stdin {
+            let (deleted, skipped) = match delete_branch(&mut git, &params, branch_name)? {
+                Outcome::Deleted(remote) => (vec![(branch_name.clone(), remote)], Vec::new()),
+                Outcome::Skipped(reason) => (Vec::new(), vec![(branch_name.clone(), reason)]),
+            };
+            print_json(&params, &pruned, deleted, skipped, Vec::new());
+            return Ok(());
+        }
+    }
 
-        remote.push(
-            &[&format!("+:refs/heads/{}", branch_name)],
-            Some(&mut push_options),
-        )?;
-        println!("Upstream deleted: {}", upstream_name);
+    let mut deleted = Vec::new();
+    let mut skipped = Vec::new();
+    let mut failed = Vec::new();
+    for name in branch_names.iter() {
+        match delete_branch(&mut git, &params, name) {
+            Ok(Outcome::Deleted(remote)) => deleted.push((name.clone(), remote)),
+            Ok(Outcome::Skipped(reason)) => skipped.push((name.clone(), reason)),
+            Err(err) => {
+                eprintln!("{}: {}", name, err);
+                failed.push((name.clone(), err.to_string()));
+            }
+        }
     }
 
-    branch.delete();
-    println!("Local branch deleted: {}", branch_name);
+    if branch_names.len() > 1 {
+        say!(
+            "{} local branch(es) and {} remote branch(es) {}, {} skipped.",
+            deleted.len(),
+            deleted
+                .iter()
+                .filter(|(_, remote)| remote.is_some())
+                .count(),
+            if params.dry_run {
+                "would be deleted"
+            } else {
+                "deleted"
+            },
+            skipped.len()
+        );
+    }
+    let failed_count = failed.len();
+    print_json(&params, &pruned, deleted, skipped, failed);
+    if failed_count > 0 {
+        bail!(
+            "{} of {} branch(es) could not be deleted",
+            failed_count,
+            branch_names.len()
+        );
+    }
 
     Ok(())
 }
+
+/// The deleted branches are given with the remote branch deleted with them, the skipped ones with
+/// the reason and the failed ones with their error. With --dry-run, `deleted` has the branches
+/// that would be deleted.
+fn print_json(
+    params: &Delete,
+    pruned: &[String],
+    deleted: Vec<(String, Option<String>)>,
+    skipped: Vec<(String, String)>,
+    failed: Vec<(String, String)>,
+) {
+    if !output::is_json() {
+        return;
+    }
+
+    let deleted = deleted
+        .into_iter()
+        .map(|(branch, remote)| {
+            Json::Object(vec![("branch", branch.into()), ("remote", remote.into())])
+        })
+        .collect::<Vec<_>>();
+    let skipped = skipped
+        .into_iter()
+        .map(|(branch, reason)| {
+            Json::Object(vec![("branch", branch.into()), ("reason", reason.into())])
+        })
+        .collect::<Vec<_>>();
+    let failed = failed
+        .into_iter()
+        .map(|(branch, error)| {
+            Json::Object(vec![("branch", branch.into()), ("error", error.into())])
+        })
+        .collect::<Vec<_>>();
+    output::print(Json::Object(vec![
+        ("deleted", deleted.into()),
+        ("skipped", skipped.into()),
+        ("failed", failed.into()),
+        ("pruned", pruned.to_vec().into()),
+        ("dry_run", params.dry_run.into()),
+    ]));
+}
+
+/// What has been done with a branch given to the command.
+enum Outcome {
+    /// With the remote-tracking branch of the remote branch deleted too, if any.
+    Deleted(Option<String>),
+    /// With the reason why the branch has been left untouched.
+    Skipped(String),
+}
+
+fn delete_branch(git: &mut Git, params: &Delete, branch_name: &str) -> Result<Outcome> {
+    let age = if let Some(older_than) = params.older_than {
+        let age = commit_age(&branch_commit(git, branch_name)?);
+
+        // NOTE: the recent branches are what --older-than is for, they are not failures
+        if age < older_than {
+            let reason = format!("last commit is too recent ({} old)", format_age(age));
+            say!("Skipped {}: {}", branch_name, reason);
+            return Ok(Outcome::Skipped(reason));
+        }
+
+        Some(age)
+    } else {
+        None
+    };
+
+    if params.keep_commits && !params.force {
+        // NOTE: the refs deleted by this command don't keep the commits
+        let mut deleted_refs = vec![format!("refs/heads/{}", branch_name)];
+        deleted_refs.extend(remote_ref(git, params, branch_name)?);
+
+        let tip = branch_commit(git, branch_name)?.id();
+        let count = unreachable_commits(&git.repo, tip, &deleted_refs)?;
+        if count > 0 {
+            bail!(
+                "Aborted: {} commit(s) of {} would become unreachable (use --force to delete anyway)",
+                count,
+                branch_name
+            );
+        }
+    }
+
+    if params.dry_run {
+        // NOTE: the remote is not queried, the remote branch may already be deleted
+        let remote = remote_ref(git, params, branch_name)?;
+        match remote.as_deref() {
+            Some(remote) => say!("Would delete: {} (and {})", branch_name, remote),
+            None => say!("Would delete: {}", branch_name),
+        }
+
+        return Ok(Outcome::Deleted(remote));
+    }
+
+    // NOTE: the remote is not even checked with --no-remote, its upstream may be gone
+    let outcome = git
+        .delete_branch(branch_name, !params.no_remote, params.remote.as_deref())
+        .map_err(|err| anyhow!("{}", err.message()))?;
+    // NOTE: with --remote the branch is not necessarily the upstream
+    let remote_kind = if params.remote.is_some() {
+        "Remote branch"
+    } else {
+        "Upstream"
+    };
+    let mut remote_deleted = None;
+    match outcome.remote {
+        RemoteDeletion::None => {}
+        RemoteDeletion::Deleted(name) => {
+            say!("{} deleted: {}", remote_kind, name);
+            remote_deleted = Some(name);
+        }
+        RemoteDeletion::AlreadyDeleted(name) => {
+            say!("{} already deleted: {}", remote_kind, name);
+        }
+        RemoteDeletion::RemoteGone(name) => {
+            eprintln!(
+                "Warning: the remote of the upstream {} does not exist anymore.",
+                name
+            );
+        }
+    }
+
+    let mut details = Vec::new();
+    if let Some(age) = age {
+        details.push(format!("last commit {} ago", format_age(age)));
+    }
+    if params.no_remote {
+        details.push("the remote has been left untouched".to_string());
+    }
+    if details.is_empty() {
+        say!("Local branch deleted: {}", outcome.branch_name);
+    } else {
+        say!(
+            "Local branch deleted: {} ({})",
+            outcome.branch_name,
+            details.join(", ")
+        );
+    }
+
+    Ok(Outcome::Deleted(remote_deleted))
+}
+
+/// The remote-tracking branch of the remote branch deleted with `branch_name`, if any.
+fn remote_ref(git: &Git, params: &Delete, branch_name: &str) -> Result<Option<String>> {
+    if let Some(remote_name) = params.remote.as_deref() {
+        return Ok(Some(format!(
+            "refs/remotes/{}/{}",
+            remote_name, branch_name
+        )));
+    }
+    // NOTE: an upstream on the local repository (remote `.`) is a local branch, never deleted
+    if params.no_remote || git.get_branch_config(branch_name, "remote")?.as_deref() == Some(".") {
+        return Ok(None);
+    }
+
+    Ok(git
+        .upstream_of(branch_name)?
+        .map(|x| format!("refs/remotes/{}", x)))
+}
+
+fn branch_commit<'a>(git: &'a Git, branch_name: &str) -> Result<git2::Commit<'a>> {
+    git.repo
+        .find_branch(branch_name, git2::BranchType::Local)
+        .with_context(|| format!("Could not find local branch: {}", branch_name))?
+        .get()
+        .peel_to_commit()
+        .context("Could not find the last commit of the branch")
+}
+
+/// Number of commits reachable from `tip` but not from any other reference than `excluded`.
+fn unreachable_commits(
+    repo: &git2::Repository,
+    tip: git2::Oid,
+    excluded: &[String],
+) -> Result<usize> {
+    let mut revwalk = repo.revwalk()?;
+    revwalk.push(tip)?;
+    for reference in repo.references()? {
+        let reference = reference?;
+        if reference
+            .name()
+            .map(|x| excluded.iter().any(|y| x == y))
+            .unwrap_or(false)
+        {
+            continue;
+        }
+        if let Ok(commit) = reference.peel_to_commit() {
+            revwalk.hide(commit.id())?;
+        }
+    }
+
+    Ok(revwalk.count())
+}
+
+fn commit_age(commit: &git2::Commit) -> Duration {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64;
+
+    Duration::from_secs((now - commit.time().seconds()).max(0) as u64)
+}
+
+fn format_age(age: Duration) -> String {
+    match age.as_secs() / 86400 {
+        0 => "less than a day".to_string(),
+        1 => "1 day".to_string(),
+        days => format!("{} days", days),
+    }
+}
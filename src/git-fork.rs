@@ -1,9 +1,12 @@
 mod common;
 
-use common::Git;
+use common::output::{self, Json};
+use common::{say, Git};
 
 use std::env;
 use std::io::Write;
+use std::path::PathBuf;
+use std::process::Command;
 use structopt::StructOpt;
 
 #[derive(StructOpt, Debug)]
@@ -12,6 +15,48 @@ use structopt::StructOpt;
     about = env!("CARGO_PKG_DESCRIPTION")
 )]
 pub struct Fork {
+    /// Run as if the command was started in <path> instead of the current working directory.
+    #[structopt(short = "C", long = "repo", value_name = "path", default_value = ".")]
+    repo: PathBuf,
+
+    /// Warn when a submodule is not initialized or has uncommitted changes.
+    #[structopt(long)]
+    check_submodules: bool,
+
+    /// Abort when a submodule is not initialized or has uncommitted changes.
+    #[structopt(long)]
+    strict: bool,
+
+    /// Update the default branch of origin (refs/remotes/origin/HEAD) from the remote first.
+    #[structopt(long)]
+    update_head: bool,
+
+    /// Reset the branch if it already exists.
+    #[structopt(long, short = "f")]
+    force: bool,
+
+    /// Stash the uncommitted changes before creating the branch and restore them on the new
+    /// branch.
+    #[structopt(long)]
+    autostash: bool,
+
+    /// Push the new branch to origin and set it as upstream.
+    #[structopt(long)]
+    push: bool,
+
+    /// Push the new branch and run the command of `fork.pr-command` to open a pull request.
+    ///
+    /// The command is run by `sh` with the name of the branch as `$1` (e.g.
+    /// `gh pr create --draft --head "$1"`). The URL to open a pull request is printed instead if
+    /// the command is not configured.
+    #[structopt(long)]
+    pr: bool,
+
+    /// Print the created branch, its base and whether it has been pushed as a JSON object. (Can
+    /// also be enabled with `GIT_TOOLS_JSON=1`.)
+    #[structopt(long)]
+    json: bool,
+
     branch_name: String,
     from: Option<String>,
 }
@@ -27,44 +72,165 @@ const FAILURE: i32 = 1;
 
 fn execute() -> i32 {
     let opts = Fork::from_args();
+    output::init(opts.json);
+
+    match run(opts) {
+        Ok(exit_status) => exit_status,
+        Err(err) => {
+            output::print_error(&err);
+
+            FAILURE
+        }
+    }
+}
+
+pub fn run(params: Fork) -> Result<i32, Box<dyn std::error::Error>> {
+    let mut git = Git::open_at(&params.repo)?;
+
+    if git.is_detached {
+        eprintln!("HEAD is detached: the commits not on any branch will not be reachable anymore.");
+    }
+
+    if params.check_submodules || params.strict {
+        git.check_submodules(params.strict)?;
+    }
 
-    if let Err(err) = run(opts) {
-        eprintln!("{}", err);
+    let stashed = if git.has_file_changes()? {
+        if !params.autostash {
+            return Err("The repository has not committed changes, aborting.".into());
+        }
+        git.stash_push("git fork --autostash")?
+    } else {
+        false
+    };
+
+    let branch_name = params.branch_name.as_str();
+    // NOTE: the changes are restored even if the branch could not be created
+    let created = create_branch(&mut git, &params);
+    if stashed {
+        if let Err(err) = git.stash_pop() {
+            return Err(format!(
+                "{}: the uncommitted changes are kept in the stash.",
+                err.message()
+            )
+            .into());
+        }
+        say!("The uncommitted changes have been restored.");
+    }
+    let (base, fork_point) = created?;
 
-        FAILURE
+    let mut pushed = false;
+    let mut pull_request_url = None;
+    let exit_status = if params.push || params.pr {
+        publish(&mut git, &params, &mut pushed, &mut pull_request_url)?
     } else {
         SUCCESS
+    };
+
+    if output::is_json() {
+        output::print(Json::Object(vec![
+            ("branch", branch_name.into()),
+            ("base", base.into()),
+            ("fork_point", fork_point.into()),
+            ("pushed", pushed.into()),
+            ("pull_request_url", pull_request_url.into()),
+        ]));
     }
+
+    Ok(exit_status)
 }
 
-pub fn run(params: Fork) -> Result<(), Box<dyn std::error::Error>> {
-    let mut git = Git::open()?;
+/// Push the branch and open a pull request if requested.
+fn publish(
+    git: &mut Git,
+    params: &Fork,
+    pushed: &mut bool,
+    pull_request_url: &mut Option<String>,
+) -> Result<i32, Box<dyn std::error::Error>> {
+    let branch_name = params.branch_name.as_str();
+
+    // NOTE: the branch is kept even if the push fails, it can be pushed again with `git push2`
+    if let Err(err) = git.push_set_upstream("origin", branch_name) {
+        eprintln!(
+            "Could not push branch {} to origin: {}",
+            branch_name,
+            err.message()
+        );
+        return Ok(FAILURE);
+    }
+    *pushed = true;
+    say!("Branch {} pushed to origin.", branch_name);
+
+    if !params.pr {
+        return Ok(SUCCESS);
+    }
 
-    if git.has_file_changes()? {
-        return Err("The repository has not committed changes, aborting.".into());
+    match git.config.get_string("fork.pr-command") {
+        Ok(pr_command) => {
+            // NOTE: the branch is an argument of the shell, it is never parsed as shell code
+            let mut command = Command::new("sh");
+            command.arg("-c").arg(pr_command).arg("sh").arg(branch_name);
+            if output::is_json() {
+                command.stdout(std::io::stderr());
+            }
+            let status = command.status()?;
+
+            Ok(status.code().unwrap_or(FAILURE))
+        }
+        Err(_) => {
+            match git.compare_url("origin", branch_name)? {
+                Some(url) => {
+                    say!("Open a pull request: {}", url);
+                    *pull_request_url = Some(url);
+                }
+                None => say!("No command configured to open a pull request (fork.pr-command)."),
+            }
+
+            Ok(SUCCESS)
+        }
     }
+}
 
+/// Returns the revision the branch has been created from and the hash of the fork point.
+fn create_branch(
+    git: &mut Git,
+    params: &Fork,
+) -> Result<(String, String), Box<dyn std::error::Error>> {
     let branch_name = params.branch_name.as_str();
-    let default_branch = git.get_default_branch("origin")?;
-    let name = params
-        .from
-        .as_deref()
-        .unwrap_or_else(|| default_branch.as_str());
+    if params.update_head {
+        git.update_remote_head("origin")?;
+    }
 
-    if name.contains('/') {
-        git.update_upstream(name)?;
+    let default_branch = git.get_default_branch("origin")?;
+    let name = params.from.as_deref().unwrap_or(default_branch.as_str());
+
+    // NOTE: the other revisions are used as they are, possibly with a warning if the remote
+    //       branch could not be fetched
+    match git.fetch_remote_branch(name) {
+        Ok(_) => {}
+        Err(err) if git.repo.revparse_single(name).is_ok() => {
+            eprintln!("Warning: could not fetch {}: {}", name, err.message());
+        }
+        Err(err) => return Err(err.into()),
     }
 
     match git.get_branch_hash(name)? {
         // name is really a branch
-        Some(hash) => git.branch(branch_name, Some(hash.as_str()))?,
+        Some(hash) => git.branch_force(branch_name, Some(hash.as_str()), params.force)?,
         // name was not a branch
-        None => git.branch(branch_name, Some(name))?,
+        None => git.branch_force(branch_name, Some(name), params.force)?,
     };
 
+    // NOTE: keep track of where the branch comes from, see `git forkinfo`
+    let fork_point = git
+        .get_branch_hash(branch_name)?
+        .expect("the branch has just been created");
+    git.set_branch_config(branch_name, "forkedFrom", &fork_point)?;
+    git.set_branch_config(branch_name, "forkParent", name)?;
+
     git.switch_branch(branch_name)?;
 
-    println!("Branch {} created.", branch_name);
+    say!("Branch {} created.", branch_name);
 
-    Ok(())
+    Ok((name.to_string(), fork_point))
 }
@@ -0,0 +1,92 @@
+mod common;
+
+use common::Git;
+
+use anyhow::{bail, Context, Result};
+use std::env;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+#[structopt(
+    bin_name = "git forkinfo",
+    about = env!("CARGO_PKG_DESCRIPTION")
+)]
+pub struct ForkInfo {
+    /// Run as if the command was started in <path> instead of the current working directory.
+    #[structopt(short = "C", long = "repo", value_name = "path", default_value = ".")]
+    repo: PathBuf,
+
+    /// Branch to inspect (the current branch by default).
+    branch_name: Option<String>,
+}
+
+fn main() {
+    let exit_status = execute();
+    std::process::exit(exit_status);
+}
+
+const SUCCESS: i32 = 0;
+const FAILURE: i32 = 1;
+
+fn execute() -> i32 {
+    let opts = ForkInfo::from_args();
+
+    if let Err(err) = run(opts) {
+        eprintln!("{}", err);
+
+        FAILURE
+    } else {
+        SUCCESS
+    }
+}
+
+pub fn run(params: ForkInfo) -> Result<()> {
+    let git = Git::open_light(&params.repo).context("Could not open repository")?;
+
+    let branch_name = match params.branch_name.as_ref().or(git.branch_name.as_ref()) {
+        Some(x) => x.as_str(),
+        None => bail!("Aborted: HEAD is detached, a branch name must be provided"),
+    };
+    git.repo
+        .find_branch(branch_name, git2::BranchType::Local)
+        .with_context(|| format!("Could not find local branch: {}", branch_name))?;
+
+    let parent = git.get_branch_config(branch_name, "forkParent")?;
+    let fork_point = git.get_branch_config(branch_name, "forkedFrom")?;
+
+    let (parent, fork_point) = match (parent, fork_point) {
+        (Some(parent), Some(fork_point)) => (parent, fork_point),
+        _ => {
+            println!("No fork information recorded, using the default branch.");
+            let parent = git.get_default_branch("origin")?;
+            let fork_point = git
+                .merge_base(branch_name, &parent)?
+                .with_context(|| format!("No common history with {}", parent))?;
+
+            (parent, fork_point)
+        }
+    };
+
+    let commit = git
+        .repo
+        .revparse_single(&fork_point)
+        .and_then(|x| x.peel_to_commit())
+        .with_context(|| format!("Could not find fork point: {}", fork_point))?;
+    let (ahead, _) = git.ahead_behind(branch_name, &fork_point)?;
+    let (behind, _) = git
+        .ahead_behind(&parent, &fork_point)
+        .with_context(|| format!("Could not find parent branch: {}", parent))?;
+
+    println!("Branch:     {}", branch_name);
+    println!("Parent:     {}", parent);
+    println!(
+        "Fork point: {} {}",
+        fork_point,
+        commit.summary().unwrap_or_default()
+    );
+    println!("Ahead:      {} commit(s)", ahead);
+    println!("Behind:     {} commit(s)", behind);
+
+    Ok(())
+}
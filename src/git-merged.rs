@@ -0,0 +1,80 @@
+mod common;
+
+use common::Git;
+
+use anyhow::{Context, Result};
+use std::env;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+#[structopt(
+    bin_name = "git merged",
+    about = env!("CARGO_PKG_DESCRIPTION")
+)]
+pub struct Merged {
+    /// Run as if the command was started in <path> instead of the current working directory.
+    #[structopt(short = "C", long = "repo", value_name = "path", default_value = ".")]
+    repo: PathBuf,
+
+    /// List the branches that are not fully merged instead.
+    #[structopt(long)]
+    not: bool,
+
+    /// Revision the branches are merged into (default branch or origin/main by default).
+    target: Option<String>,
+}
+
+fn main() {
+    let exit_status = execute();
+    std::process::exit(exit_status);
+}
+
+const SUCCESS: i32 = 0;
+const FAILURE: i32 = 1;
+
+fn execute() -> i32 {
+    let opts = Merged::from_args();
+
+    if let Err(err) = run(opts) {
+        eprintln!("{}", err);
+
+        FAILURE
+    } else {
+        SUCCESS
+    }
+}
+
+pub fn run(params: Merged) -> Result<()> {
+    let git = Git::open_light(&params.repo).context("Could not open repository")?;
+
+    let target = match params.target.clone() {
+        Some(x) => x,
+        None => git.get_default_branch("origin")?,
+    };
+    let target_oid = git
+        .repo
+        .revparse_single(&target)
+        .and_then(|x| x.peel_to_commit())
+        .with_context(|| format!("Could not find revision: {}", target))?
+        .id();
+
+    for branch in git.repo.branches(Some(git2::BranchType::Local))? {
+        let (branch, _) = branch?;
+        let name = match branch.name()? {
+            Some(x) => x.to_string(),
+            None => continue,
+        };
+        let oid = branch.get().peel_to_commit()?.id();
+
+        let is_merged = oid == target_oid || git.repo.graph_descendant_of(target_oid, oid)?;
+        if is_merged == params.not {
+            continue;
+        }
+
+        let (ahead, behind) = git.repo.graph_ahead_behind(oid, target_oid)?;
+        println!("{} (ahead {}, behind {})", name, ahead, behind);
+    }
+
+    Ok(())
+}
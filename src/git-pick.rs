@@ -0,0 +1,156 @@
+mod common;
+
+use common::{Git, PickOutcome, RepositoryState};
+
+use anyhow::{bail, Context, Result};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+#[structopt(
+    bin_name = "git pick",
+    about = env!("CARGO_PKG_DESCRIPTION")
+)]
+pub struct Pick {
+    /// Run as if the command was started in <path> instead of the current working directory.
+    #[structopt(short = "C", long = "repo", value_name = "path", default_value = ".")]
+    repo: PathBuf,
+
+    /// Commit the resolved conflicts and pick the remaining commits.
+    #[structopt(long = "continue", conflicts_with = "abort")]
+    resume: bool,
+
+    /// Cancel the pick and restore HEAD as it was before.
+    #[structopt(long)]
+    abort: bool,
+
+    /// Commits to pick, in order. (A range `A..B` picks the commits of B that are not in A.)
+    #[structopt(value_name = "rev", required_unless_one = &["resume", "abort"])]
+    revisions: Vec<String>,
+}
+
+fn main() {
+    let exit_status = execute();
+    std::process::exit(exit_status);
+}
+
+const SUCCESS: i32 = 0;
+const FAILURE: i32 = 1;
+const CONFLICT: i32 = 2;
+
+fn execute() -> i32 {
+    let opts = Pick::from_args();
+
+    match run(opts) {
+        Ok(exit_status) => exit_status,
+        Err(err) => {
+            eprintln!("{}", err);
+
+            FAILURE
+        }
+    }
+}
+
+pub fn run(params: Pick) -> Result<i32> {
+    let mut git = Git::open_at(&params.repo).context("Could not open repository")?;
+
+    // NOTE: the remaining commits are stored on conflict to be able to continue
+    let todo_path = git.repo.path().join("PICK_TODO");
+    let orig_head_path = git.repo.path().join("PICK_ORIG_HEAD");
+
+    if params.abort {
+        let orig_head = fs::read_to_string(&orig_head_path)
+            .map(|x| x.trim().to_string())
+            .map_err(|_| anyhow::anyhow!("There is no pick to abort."))?;
+        let object = git.repo.revparse_single(&orig_head)?;
+        git.repo.reset(&object, git2::ResetType::Hard, None)?;
+        git.repo.cleanup_state()?;
+        remove_state(&todo_path, &orig_head_path)?;
+
+        println!("The pick has been aborted, HEAD is back to {}.", orig_head);
+        return Ok(SUCCESS);
+    }
+
+    if git.is_detached {
+        eprintln!("HEAD is detached: the picked commits will not be on any branch.");
+    }
+
+    let revisions = if params.resume {
+        let todo = fs::read_to_string(&todo_path)
+            .map_err(|_| anyhow::anyhow!("There is no pick to continue."))?;
+
+        if git.state() == RepositoryState::CherryPick {
+            let hash = git.commit_cherry_pick()?;
+            println!("Picked as {}", hash);
+        } else if let Some(state) = git.state_description() {
+            bail!("There is a {}, conclude it before continuing.", state);
+        }
+
+        todo.lines().map(|x| x.to_string()).collect::<Vec<_>>()
+    } else {
+        if todo_path.exists() {
+            bail!("A pick is in progress, use --continue or --abort.");
+        }
+        if git.has_file_changes()? {
+            bail!("Aborted: the repository has not committed changes");
+        }
+
+        let mut revisions = Vec::new();
+        for revision in params.revisions.iter() {
+            if let Some((from, to)) = revision.split_once("..") {
+                revisions.extend(git.rev_list(from, to, true)?);
+            } else {
+                let commit = git
+                    .repo
+                    .revparse_single(revision)
+                    .with_context(|| format!("Could not find revision: {}", revision))?
+                    .peel_to_commit()?;
+                revisions.push(format!("{}", commit.id()));
+            }
+        }
+        fs::write(&orig_head_path, &git.head_hash)?;
+
+        revisions
+    };
+
+    for (i, revision) in revisions.iter().enumerate() {
+        match git.cherry_pick(revision)? {
+            PickOutcome::Committed(hash) => println!("Picked {} as {}", revision, hash),
+            PickOutcome::Conflicts(paths) => {
+                let mut todo = String::new();
+                for remaining in &revisions[i + 1..] {
+                    todo.push_str(remaining);
+                    todo.push('\n');
+                }
+                fs::write(&todo_path, todo)?;
+
+                println!("Conflict while picking {}:", revision);
+                for path in paths {
+                    println!("    {}", path);
+                }
+                println!(
+                    "Resolve the conflicts and add the files, then run `git pick --continue` \
+                    ({} commit(s) remaining).",
+                    revisions.len() - i - 1
+                );
+                return Ok(CONFLICT);
+            }
+        }
+    }
+
+    remove_state(&todo_path, &orig_head_path)?;
+
+    Ok(SUCCESS)
+}
+
+fn remove_state(todo_path: &Path, orig_head_path: &Path) -> Result<()> {
+    for path in [todo_path, orig_head_path] {
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+    }
+
+    Ok(())
+}
@@ -1,10 +1,12 @@
 mod common;
 
-use common::Git;
+use common::output::{self, Json};
+use common::{say, Git, UpstreamStatus};
 
 use std::env;
-use std::io::Write;
+use std::io::{IsTerminal, Write};
 use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
 use std::process::Command;
 use structopt::{clap::AppSettings, StructOpt};
 
@@ -15,6 +17,28 @@ use structopt::{clap::AppSettings, StructOpt};
     settings = &[AppSettings::TrailingVarArg, AppSettings::AllowLeadingHyphen],
 )]
 pub struct Params {
+    /// Run as if the command was started in <path> instead of the current working directory.
+    // NOTE: no long name, `--repo` is an option of `git push` forwarded with the arguments
+    #[structopt(short = "C", value_name = "path", default_value = ".")]
+    repo: PathBuf,
+
+    /// Push to this remote instead of the remote of the upstream (origin if not set).
+    #[structopt(long, value_name = "remote")]
+    to: Option<String>,
+
+    /// Set the upstream even if the branch already has one. (Use --to to choose the remote.)
+    #[structopt(long, short = "u")]
+    set_upstream: bool,
+
+    /// Do not ask for confirmation when pushing more commits than `push2.confirm-threshold`.
+    #[structopt(long, short = "y")]
+    yes: bool,
+
+    /// Print the remote and the arguments of `git push` as a JSON object before pushing. (Can also
+    /// be enabled with `GIT_TOOLS_JSON=1`.)
+    #[structopt(long)]
+    json: bool,
+
     args: Vec<String>,
 }
 
@@ -29,9 +53,10 @@ const FAILURE: i32 = 1;
 
 fn execute() -> i32 {
     let opts = Params::from_args();
+    output::init(opts.json);
 
     if let Err(err) = run(opts) {
-        eprintln!("{}", err);
+        output::print_error(&err);
 
         FAILURE
     } else {
@@ -40,19 +65,139 @@ fn execute() -> i32 {
 }
 
 pub fn run(params: Params) -> Result<(), Box<dyn std::error::Error>> {
-    let git = Git::open()?;
-
-    Err(match (git.branch_name.as_ref(), git.upstream.as_ref()) {
-        (Some(name), None) => Command::new("git")
-            .arg("push")
-            .args(&["--set-upstream", "origin", name])
-            .args(params.args)
-            .exec()
-            .into(),
-        _ => Command::new("git")
-            .arg("push")
-            .args(params.args)
-            .exec()
-            .into(),
-    })
+    let git = Git::open_at(&params.repo)?;
+
+    if git.is_detached {
+        eprintln!("HEAD is detached: no upstream will be set.");
+    }
+
+    if !params.yes && !confirm_push(&git)? {
+        return Err("Aborted.".into());
+    }
+
+    // NOTE: the push is left to Git, it may be forced
+    match git.upstream_status()? {
+        _ if params.to.is_some() => {}
+        UpstreamStatus::Behind(behind) => eprintln!(
+            "Warning: the branch is {} commit(s) behind its upstream, the push will be rejected unless forced.",
+            behind
+        ),
+        UpstreamStatus::Diverged(_, behind) => eprintln!(
+            "Warning: the branch and its upstream have diverged, {} commit(s) of the upstream \
+            would be lost by a forced push.",
+            behind
+        ),
+        _ => {}
+    }
+
+    let mut command = Command::new("git");
+    command.arg("push");
+
+    let (remote, set_upstream) = match (git.branch_name.as_ref(), git.upstream.as_ref()) {
+        (Some(name), Some(upstream)) if params.set_upstream => {
+            let remote = params.to.as_deref().unwrap_or("origin");
+            say!(
+                "Pushing to {} (setting upstream to {}/{} instead of {})",
+                remote,
+                remote,
+                name,
+                upstream
+            );
+            command.args(["--set-upstream", remote, name]);
+            (Some(remote.to_string()), true)
+        }
+        (Some(name), None) => {
+            let remote = params.to.as_deref().unwrap_or("origin");
+            say!("Pushing to {} (setting upstream)", remote);
+            command.args(["--set-upstream", remote, name]);
+            (Some(remote.to_string()), true)
+        }
+        (Some(name), Some(_)) => {
+            if let Some(remote) = params.to.as_deref() {
+                say!("Pushing to {}", remote);
+                command.args([remote, name]);
+                (Some(remote.to_string()), false)
+            } else {
+                let remote = git
+                    .repo
+                    .branch_upstream_remote(&format!("refs/heads/{}", name))?;
+                let remote = remote.as_str().unwrap_or("origin");
+                say!("Pushing to {}", remote);
+                (Some(remote.to_string()), false)
+            }
+        }
+        (None, _) => {
+            if let Some(remote) = params.to.as_deref() {
+                say!("Pushing to {}", remote);
+                command.arg(remote);
+            }
+            (params.to.clone(), false)
+        }
+    };
+    command.args(params.args);
+
+    if output::is_json() {
+        let args = command
+            .get_args()
+            .map(|x| x.to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        output::print(Json::Object(vec![
+            ("remote", remote.into()),
+            ("set_upstream", set_upstream.into()),
+            ("args", args.into()),
+        ]));
+        std::io::stdout().flush()?;
+        // NOTE: only the JSON object is written on the standard output
+        command.stdout(std::io::stderr());
+    }
+
+    Err(command.exec().into())
+}
+
+/// Ask for confirmation if the number of commits to publish exceeds `push2.confirm-threshold`.
+///
+/// Without a terminal the push proceeds, unless `push2.confirm-non-interactive` is set.
+fn confirm_push(git: &Git) -> Result<bool, Box<dyn std::error::Error>> {
+    let threshold = match git.config.get_i64("push2.confirm-threshold") {
+        Ok(x) if x >= 0 => x as usize,
+        _ => return Ok(true),
+    };
+
+    // NOTE: a new branch publishes the commits that are not on the default branch
+    let base = match git.upstream.as_ref() {
+        Some(x) => x.clone(),
+        None => git.get_default_branch("origin")?,
+    };
+    let (ahead, _) = match git.ahead_behind("HEAD", &base) {
+        Ok(x) => x,
+        Err(err) if err.code() == git2::ErrorCode::NotFound => return Ok(true),
+        Err(err) => return Err(err.into()),
+    };
+    if ahead <= threshold {
+        return Ok(true);
+    }
+
+    if !std::io::stdin().is_terminal() {
+        let require_yes = git
+            .config
+            .get_bool("push2.confirm-non-interactive")
+            .unwrap_or_default();
+        if require_yes {
+            eprintln!(
+                "{} commit(s) would be published (more than {}), use --yes to push.",
+                ahead, threshold
+            );
+        }
+        return Ok(!require_yes);
+    }
+
+    eprint!(
+        "{} commit(s) ahead of {} would be published, continue? [y/N] ",
+        ahead, base
+    );
+    std::io::stderr().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
 }
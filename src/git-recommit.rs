@@ -0,0 +1,87 @@
+mod common;
+
+use common::Git;
+
+use anyhow::{bail, Context, Result};
+use std::env;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+#[structopt(
+    bin_name = "git recommit",
+    about = env!("CARGO_PKG_DESCRIPTION")
+)]
+pub struct Recommit {
+    /// Run as if the command was started in <path> instead of the current working directory.
+    #[structopt(short = "C", long = "repo", value_name = "path", default_value = ".")]
+    repo: PathBuf,
+
+    /// Number of commits to reword (the last one by default).
+    #[structopt(short = "n", value_name = "count", default_value = "1")]
+    count: usize,
+
+    /// New message of a commit, from the oldest to the newest (like `git rebase -i`). (Must be
+    /// repeated for each commit.)
+    #[structopt(
+        short = "m",
+        long = "message",
+        value_name = "message",
+        number_of_values = 1
+    )]
+    messages: Vec<String>,
+}
+
+fn main() {
+    let exit_status = execute();
+    std::process::exit(exit_status);
+}
+
+const SUCCESS: i32 = 0;
+const FAILURE: i32 = 1;
+
+fn execute() -> i32 {
+    let opts = Recommit::from_args();
+
+    if let Err(err) = run(opts) {
+        eprintln!("{}", err);
+
+        FAILURE
+    } else {
+        SUCCESS
+    }
+}
+
+pub fn run(params: Recommit) -> Result<()> {
+    let mut git = Git::open_at(&params.repo).context("Could not open repository")?;
+    if git.is_detached {
+        eprintln!("HEAD is detached: the reworded commits will not be on any branch.");
+    }
+
+    if params.count == 0 {
+        bail!("Aborted: at least 1 commit must be reworded");
+    }
+
+    if params.messages.len() != params.count {
+        bail!(
+            "Aborted: {} message(s) given for {} commit(s)",
+            params.messages.len(),
+            params.count
+        );
+    }
+
+    if git.has_file_changes()? {
+        bail!("Aborted: the repository has not committed changes");
+    }
+
+    let messages = params
+        .messages
+        .iter()
+        .map(|x| x.as_str())
+        .collect::<Vec<_>>();
+    let head = git.reword(&messages)?;
+
+    println!("{} commit(s) reworded, HEAD is now {}.", params.count, head);
+
+    Ok(())
+}
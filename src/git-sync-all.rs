@@ -0,0 +1,224 @@
+mod common;
+
+use common::{merge_message, Git, Repository};
+
+use anyhow::{bail, Result};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use structopt::StructOpt;
+
+#[derive(StructOpt, Debug)]
+#[structopt(
+    bin_name = "git sync-all",
+    about = env!("CARGO_PKG_DESCRIPTION")
+)]
+pub struct SyncAll {
+    /// Number of repositories updated at the same time.
+    #[structopt(long, short = "j", value_name = "count", default_value = "1")]
+    jobs: usize,
+
+    /// Directory where the repositories are searched (recursively).
+    #[structopt(value_name = "dir", default_value = ".")]
+    dir: PathBuf,
+}
+
+fn main() {
+    let exit_status = execute();
+    std::process::exit(exit_status);
+}
+
+const SUCCESS: i32 = 0;
+const FAILURE: i32 = 1;
+
+fn execute() -> i32 {
+    let opts = SyncAll::from_args();
+
+    match run(opts) {
+        Ok(exit_status) => exit_status,
+        Err(err) => {
+            eprintln!("{}", err);
+
+            FAILURE
+        }
+    }
+}
+
+enum Outcome {
+    UpToDate,
+    Advanced(usize),
+    Conflict { advanced: usize, behind: usize },
+    Skipped(&'static str),
+    Failed(String),
+}
+
+pub fn run(params: SyncAll) -> Result<i32> {
+    if !params.dir.is_dir() {
+        bail!(
+            "Could not search the repositories in {:?}: not a directory",
+            params.dir
+        );
+    }
+    let mut repositories = Vec::new();
+    find_repositories(&params.dir, &mut repositories);
+    repositories.sort();
+
+    // NOTE: each repository is updated by a single thread, the results keep the initial order
+    let queue = Arc::new(Mutex::new(
+        repositories.iter().cloned().enumerate().collect::<Vec<_>>(),
+    ));
+    let results = Arc::new(Mutex::new(Vec::new()));
+    let workers = (0..params.jobs.max(1))
+        .map(|_| {
+            let queue = Arc::clone(&queue);
+            let results = Arc::clone(&results);
+            thread::spawn(move || loop {
+                let next = queue.lock().unwrap().pop();
+                let (i, path) = match next {
+                    Some(x) => x,
+                    None => break,
+                };
+
+                let outcome = sync(&path).unwrap_or_else(|err| Outcome::Failed(err.to_string()));
+                results.lock().unwrap().push((i, outcome));
+            })
+        })
+        .collect::<Vec<_>>();
+    for worker in workers {
+        worker.join().expect("a worker panicked");
+    }
+
+    let mut results = Arc::try_unwrap(results)
+        .ok()
+        .expect("the workers are done")
+        .into_inner()
+        .unwrap();
+    results.sort_by_key(|(i, _)| *i);
+
+    let names = repositories
+        .iter()
+        .map(|x| {
+            x.strip_prefix(&params.dir)
+                .ok()
+                .filter(|x| !x.as_os_str().is_empty())
+                .unwrap_or(x)
+                .display()
+                .to_string()
+        })
+        .collect::<Vec<_>>();
+    let width = names.iter().map(|x| x.len()).max().unwrap_or_default();
+
+    let mut exit_status = SUCCESS;
+    for (i, outcome) in results {
+        let status = match outcome {
+            Outcome::UpToDate => "up-to-date".to_string(),
+            Outcome::Advanced(count) => format!("advanced {}", count),
+            Outcome::Conflict { advanced, behind } => {
+                format!("conflict (advanced {}, behind {})", advanced, behind)
+            }
+            Outcome::Skipped(reason) => format!("skipped ({})", reason),
+            Outcome::Failed(err) => {
+                exit_status = FAILURE;
+                format!("failed: {}", err)
+            }
+        };
+        println!("{:width$}  {}", names[i], status, width = width);
+    }
+
+    Ok(exit_status)
+}
+
+/// Fetch the default branch and merge as many commits as possible without conflict, like
+/// `git try-merge --no-merge`.
+fn sync(path: &Path) -> Result<Outcome> {
+    // NOTE: the current working directory is shared by the threads, it must not be changed;
+    //       GIT_DIR is ignored, every thread would open the same repository
+    let mut git = Git::from_repo(Repository::open(path)?)?;
+
+    if git.is_detached {
+        return Ok(Outcome::Skipped("HEAD is detached"));
+    }
+    if git.has_file_changes()? {
+        return Ok(Outcome::Skipped("uncommitted changes"));
+    }
+
+    let top_rev = git.get_default_branch("origin")?;
+    git.update_upstream(&top_rev)?;
+
+    let mut rev_list = git.rev_list("HEAD", &top_rev, true)?;
+    let behind_before = rev_list.len();
+    if rev_list.is_empty() {
+        return Ok(Outcome::UpToDate);
+    }
+
+    let ignore_conflict_set = git.glob_set("try-merge.ignore-conflict", &[], false)?;
+    let resolution = git.conflict_resolution()?;
+    let regenerate_commands = git.regenerate_commands()?;
+    let upstream_first = git
+        .config
+        .get_bool("try-merge.first-parent-upstream")
+        .unwrap_or_default();
+    while let Some(revision) = rev_list.pop() {
+        // NOTE: same message as `git try-merge`, these merge commits can be squashed by it
+        let message = merge_message(
+            &format!("Merge commit {} (no conflict)", revision),
+            &revision,
+            false,
+        );
+        if git
+            .merge_no_conflict(
+                &revision,
+                &message,
+                &ignore_conflict_set,
+                resolution,
+                &regenerate_commands,
+                false,
+                false,
+                upstream_first,
+            )?
+            .is_some()
+        {
+            break;
+        }
+    }
+
+    let behind = git.count_commits_between("HEAD", &top_rev)?;
+    let advanced = behind_before - behind;
+    if behind == 0 {
+        Ok(Outcome::Advanced(advanced))
+    } else {
+        Ok(Outcome::Conflict { advanced, behind })
+    }
+}
+
+/// The repositories are not searched inside other repositories. The directories that cannot be
+/// read are reported and skipped.
+fn find_repositories(dir: &Path, repositories: &mut Vec<PathBuf>) {
+    if dir.join(".git").exists() {
+        repositories.push(dir.to_path_buf());
+        return;
+    }
+
+    let entries = match fs::read_dir(dir) {
+        Ok(x) => x,
+        Err(err) => {
+            eprintln!("Warning: could not read {}: {}", dir.display(), err);
+            return;
+        }
+    };
+    for entry in entries {
+        let entry = match entry {
+            Ok(x) => x,
+            Err(err) => {
+                eprintln!("Warning: could not read {}: {}", dir.display(), err);
+                continue;
+            }
+        };
+        // NOTE: the symlinks are not followed to avoid loops
+        if entry.file_type().map(|x| x.is_dir()).unwrap_or(false) {
+            find_repositories(&entry.path(), repositories);
+        }
+    }
+}
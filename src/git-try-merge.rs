@@ -1,11 +1,18 @@
 mod common;
 
-use common::Git;
+use common::output::{self, Json};
+use common::{
+    append_trailers, merge_message, say, ConflictResolution, Git, MergeReport, Repository,
+    RepositoryState, MERGE_TRAILER,
+};
 
-use globset::{Glob, GlobSetBuilder};
-use std::collections::HashSet;
-use std::io::Write;
+use globset::{Glob, GlobMatcher, GlobSet, GlobSetBuilder};
+use std::collections::BTreeSet;
+use std::env;
+use std::fs;
+use std::io::{IsTerminal, Write};
 use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
 use std::process::Command;
 use structopt::{clap::AppSettings, StructOpt};
 
@@ -25,14 +32,206 @@ pub struct TryMerge {
     /// Or for this repository only:
     ///
     /// git config try-merge.squash true
+    ///
+    /// Squashing is never done on the branches matching the glob patterns of
+    /// `try-merge.no-squash-branches`.
+    ///
+    /// Only the last merge commits with a `Try-Merge: auto` trailer are squashed (keep it when
+    /// committing a conflict resolution).
     #[structopt(long)]
     squash: bool,
 
-    // NOTE: the long and short name for the parameters must not conflict with `git merge`
+    // NOTE: the long and short name for the parameters must not conflict with `git merge`, the
+    //       only exception is --autostash: try-merge stashes the changes itself and never
+    //       forwards it to `git merge`
     /// Do not run `git merge` at the end. (Merge to the latest commit possible without conflict.)
     #[structopt(long, short = "u")]
     no_merge: bool,
 
+    /// Exit with a status code describing the outcome instead of handing over to `git merge`.
+    ///
+    /// 0: the branch is up-to-date or has been merged without conflict, 1: an error occurred,
+    /// 2: the merge stopped at a conflict that needs to be resolved manually.
+    #[structopt(long)]
+    exit_code: bool,
+
+    /// Stash the uncommitted changes before merging and restore them afterwards. (They are kept
+    /// in the stash if a conflict has to be resolved.)
+    ///
+    /// The stash is handled by try-merge itself: the option is never passed to `git merge`.
+    #[structopt(long)]
+    autostash: bool,
+
+    /// Print the outcome (merged revision, skipped commits, ignored conflicts) as a JSON object.
+    /// (Can also be enabled with `GIT_TOOLS_JSON=1`.)
+    #[structopt(long, conflicts_with_all = &["find-frontier", "dry-run"])]
+    json: bool,
+
+    /// Do not print the progress of the merge. (Also passed to `git merge` at the end.)
+    #[structopt(long)]
+    quiet: bool,
+
+    /// Continue to merge to the revision of the previous run once its conflicts have been
+    /// resolved and committed. (Not `--continue`, which concludes the merge with `git merge`.)
+    #[structopt(long)]
+    resume: bool,
+
+    /// Make a single merge commit for the whole range if it can be merged without conflict.
+    #[structopt(long)]
+    single_commit: bool,
+
+    /// Do not merge further than this revision. (It must be between HEAD and the revision for the
+    /// update.)
+    #[structopt(long, value_name = "rev")]
+    until: Option<String>,
+
+    /// Merge at most this number of commits, the next ones are left for another run.
+    #[structopt(long, value_name = "count")]
+    limit: Option<usize>,
+
+    /// Only look for the commits to merge after this revision instead of HEAD. (It must be an
+    /// ancestor of the revision for the update.)
+    ///
+    /// The commits already merged in HEAD are skipped.
+    #[structopt(long, value_name = "base")]
+    onto: Option<String>,
+
+    /// Switch to this local branch first and update it instead of the current branch. (The
+    /// current branch is restored if the update fails.) Cannot be used with --dry-run nor
+    /// --find-frontier, which never check out anything.
+    #[structopt(
+        long,
+        value_name = "branch",
+        conflicts_with_all = &["autostash", "resume", "dry-run", "find-frontier"]
+    )]
+    into: Option<String>,
+
+    /// Add a note to the merge commit (or the squash commit) with the list of the merged
+    /// revisions. (See `git notes show`.)
+    #[structopt(long)]
+    notes: bool,
+
+    /// Use the author of the merged revision as author of the merge commits. (You remain the
+    /// committer.)
+    #[structopt(long)]
+    preserve_author: bool,
+
+    /// Warn when a submodule is not initialized or has uncommitted changes.
+    #[structopt(long)]
+    check_submodules: bool,
+
+    /// Abort when a submodule is not initialized or has uncommitted changes.
+    #[structopt(long)]
+    strict: bool,
+
+    /// Add a `Try-Merge-Revision: <hash>` trailer with the merged revision to the merge commit
+    /// messages. (The squash commit has a trailer for each revision.)
+    #[structopt(long)]
+    trailer: bool,
+
+    /// Take the files matching this glob from the upstream (see --conflict-resolution) when they
+    /// are conflicting, in addition to the globs of `try-merge.ignore-conflict`. (Can be
+    /// repeated.)
+    #[structopt(long = "ignore-conflict", value_name = "glob", number_of_values = 1)]
+    ignore_conflicts: Vec<String>,
+
+    /// Ignore the globs of `try-merge.ignore-conflict`, only use the ones given with
+    /// --ignore-conflict.
+    #[structopt(long)]
+    ignore_conflict_only: bool,
+
+    /// Keep `theirs` (the upstream) or `ours` (the current branch) version of the files whose
+    /// conflicts are ignored. (Overrides `try-merge.conflict-resolution`, `theirs` by default.)
+    #[structopt(long, value_name = "side")]
+    conflict_resolution: Option<ConflictResolution>,
+
+    /// Delete the local branches merged into the default branch once the branch is up-to-date.
+    #[structopt(long)]
+    prune_merged: bool,
+
+    /// Also delete the upstream of the merged branches. (Uses `git delete`.)
+    #[structopt(long, requires = "prune-merged")]
+    prune_remote: bool,
+
+    /// Only show what would be merged and the branches that --prune-merged would delete: nothing
+    /// is committed, checked out nor deleted.
+    #[structopt(long)]
+    dry_run: bool,
+
+    /// Add a `Co-authored-by` trailer to the squash commit for each author of the merged commits.
+    #[structopt(long)]
+    co_authors: bool,
+
+    /// Run this command after each merge commit to verify it (e.g. `cargo test`).
+    ///
+    /// If the command fails, the merge commit is undone with a soft reset (its changes remain
+    /// staged) and the merge stops. Note that the command runs for every merge commit, a slow
+    /// build makes the whole merge slow.
+    #[structopt(long, value_name = "command")]
+    verify_command: Option<String>,
+
+    /// Update the default branch of origin (refs/remotes/origin/HEAD) from the remote first.
+    #[structopt(long)]
+    update_head: bool,
+
+    /// Fetch all the remotes first. The remotes that cannot be fetched are reported but do not
+    /// stop the merge.
+    #[structopt(long)]
+    fetch_all: bool,
+
+    /// Only print the furthest revision that can be merged without conflict and the number of
+    /// commits up to it. (Nothing is merged.)
+    ///
+    /// The revision is found by a binary search: it assumes that once a commit conflicts, all the
+    /// commits after it conflict too.
+    #[structopt(long)]
+    find_frontier: bool,
+
+    /// Merge the first conflicting commit without running `git merge`: the conflicts are written
+    /// in the working directory, resolve them and commit. (The arguments for `git merge` cannot
+    /// be used.)
+    #[structopt(long)]
+    internal_merge: bool,
+
+    /// Do not stop at the first conflicting commit: merge as far as possible without conflict,
+    /// then list the commits that introduce conflicts instead of running `git merge`.
+    ///
+    /// A merge commit includes all the commits before the merged revision: the conflicting
+    /// commits can't be left out, only the revisions after them that still merge without
+    /// conflict can be merged.
+    #[structopt(long)]
+    keep_going: bool,
+
+    /// Ignore the whitespace changes when merging: the conflicts made only of whitespace changes
+    /// (indentation, line endings) are resolved. The other conflicts are not affected. (Passed to
+    /// `git merge` as `-Xignore-all-space`.)
+    #[structopt(long)]
+    ignore_whitespace: bool,
+
+    /// Show all the files with ignored conflicts instead of only the first ones.
+    #[structopt(long)]
+    show_all_ignored: bool,
+
+    /// Only stop at the commits touching this path. (Can be repeated.)
+    ///
+    /// The other commits are merged along with the next commit touching the path: the result
+    /// still merges the full tree, not only the given paths.
+    #[structopt(long = "path", value_name = "pathspec", number_of_values = 1)]
+    paths: Vec<String>,
+
+    /// Run as if the command was started in <path> instead of the current working directory.
+    #[structopt(short = "C", long = "repo", value_name = "path", default_value = ".")]
+    repo: PathBuf,
+
+    /// Candidate revision for the update, the first one that exists is used. (Can be repeated.)
+    #[structopt(
+        long = "target",
+        value_name = "rev",
+        number_of_values = 1,
+        conflicts_with = "revision"
+    )]
+    targets: Vec<String>,
+
     /// Revision for the update (default branch or origin/main by default).
     revision: Option<String>,
 
@@ -47,117 +246,993 @@ fn main() {
 
 const SUCCESS: i32 = 0;
 const FAILURE: i32 = 1;
+const CONFLICT: i32 = 2;
+
+const MAX_IGNORED_CONFLICTS_SHOWN: usize = 10;
 
 fn execute() -> i32 {
     let opts = TryMerge::from_args();
 
-    if let Err(err) = run(opts) {
-        eprintln!("{}", err);
+    output::init(opts.json);
 
-        FAILURE
-    } else {
-        SUCCESS
+    match run(opts) {
+        Ok(exit_status) => exit_status,
+        Err(err) => {
+            output::print_error(&err);
+
+            FAILURE
+        }
     }
 }
 
-pub fn run(params: TryMerge) -> Result<(), Box<dyn std::error::Error>> {
-    let git = Git::open()?;
+pub fn run(params: TryMerge) -> Result<i32, Box<dyn std::error::Error>> {
+    let mut outcome = None;
+    let exit_status = try_merge(params, &mut outcome)?;
+    if let Some(outcome) = outcome {
+        output::print(outcome);
+    }
 
-    update_branch(git, params)
+    Ok(exit_status)
 }
 
-fn update_branch(mut git: Git, params: TryMerge) -> Result<(), Box<dyn std::error::Error>> {
-    let default_branch = git.get_default_branch("origin")?;
-    let top_rev = params.revision.clone().unwrap_or_else(|| default_branch);
+fn try_merge(
+    params: TryMerge,
+    outcome: &mut Option<Json>,
+) -> Result<i32, Box<dyn std::error::Error>> {
+    let mut git = Git::open_at(&params.repo)?;
+    git.merge_ignore_whitespace = params.ignore_whitespace;
+
+    if let Some(into) = params.into.clone() {
+        if git.has_file_changes()? {
+            return Err("The repository has not committed changes, aborting.".into());
+        }
+        let original = git
+            .branch_name
+            .clone()
+            .unwrap_or_else(|| git.head_hash.clone());
+        git.switch_branch(&into)
+            .map_err(|err| format!("Could not switch to branch {}: {}", into, err.message()))?;
+        say!("Switched to branch {}.", into);
+
+        let git_dir = git.repo.path().to_path_buf();
+        let res = update_branch(git, params, outcome);
+        // NOTE: the update also fails with an exit status (e.g. a conflict or a failed
+        //       --verify-command)
+        if !matches!(res, Ok(SUCCESS)) {
+            // NOTE: a merge in progress is left as is to be concluded on the branch
+            let mut git = Git::from_repo(Repository::open(&git_dir)?)?;
+            if git.state() == RepositoryState::Clean {
+                // NOTE: the worktree was clean, the changes left are those of the update (e.g. the
+                //       merge undone by --verify-command) and they would be carried to the other
+                //       branch
+                if git.has_file_changes()? {
+                    let head = git.repo.head()?.peel_to_commit()?;
+                    git.repo
+                        .reset(head.as_object(), git2::ResetType::Hard, None)?;
+                    eprintln!("The changes left on {} have been discarded.", into);
+                }
+                git.set_head(&original, false)?;
+                eprintln!("Back to {}.", original);
+            }
+        }
+
+        return res;
+    }
+
+    if git.is_detached {
+        eprintln!("HEAD is detached: the merge commits will not be on any branch.");
+    }
+
+    let read_only = params.find_frontier || params.dry_run;
+    let stashed = if params.autostash && !read_only && git.has_file_changes()? {
+        git.stash_push("git try-merge --autostash")?
+    } else {
+        false
+    };
+    if !stashed {
+        return update_branch(git, params, outcome);
+    }
+
+    // NOTE: the changes are restored even if the update failed
+    let git_dir = git.repo.path().to_path_buf();
+    let res = update_branch(git, params, outcome);
+    let mut git = Git::from_repo(Repository::open(&git_dir)?)?;
+    if git.state() == RepositoryState::Merge {
+        say!(
+            "The uncommitted changes are kept in the stash, use `git stash pop` once the merge \
+            is concluded."
+        );
+    } else if let Err(err) = git.stash_pop() {
+        return Err(format!(
+            "{}: the uncommitted changes are kept in the stash.",
+            err.message()
+        )
+        .into());
+    } else {
+        say!("The uncommitted changes have been restored.");
+    }
+
+    res
+}
+
+fn update_branch(
+    mut git: Git,
+    params: TryMerge,
+    outcome: &mut Option<Json>,
+) -> Result<i32, Box<dyn std::error::Error>> {
+    // NOTE: the revision is stored when handing over to `git merge` to be able to continue
+    let target_path = git.repo.path().join("TRY_MERGE_TARGET");
+    let previous_top_rev = fs::read_to_string(&target_path)
+        .ok()
+        .map(|x| x.trim().to_string());
+
+    if params.internal_merge && !params.merge_args.is_empty() {
+        return Err("The arguments for `git merge` cannot be used with --internal-merge.".into());
+    }
+
+    if params.resume && params.onto.is_some() {
+        return Err("The option --onto cannot be used with --resume.".into());
+    }
+
+    if params.update_head {
+        git.update_remote_head("origin")?;
+    }
+
+    // NOTE: the remotes that could be fetched are still used
+    if params.fetch_all {
+        if let Err(err) = git.fetch_all() {
+            eprintln!("Warning: {}.", err.message());
+        }
+    }
+
+    let top_rev = match params.revision.clone() {
+        Some(x) => x,
+        None if params.resume => {
+            if let Some(state) = git.state_description() {
+                return Err(format!("There is a {}, conclude it before continuing.", state).into());
+            }
+            previous_top_rev.ok_or("There is no merge to continue.")?
+        }
+        None if !params.targets.is_empty() => {
+            match params
+                .targets
+                .iter()
+                .find(|x| git.repo.revparse_single(x).is_ok())
+            {
+                Some(x) => x.clone(),
+                None => {
+                    return Err(
+                        format!("None of the targets exist: {}", params.targets.join(", ")).into(),
+                    )
+                }
+            }
+        }
+        None => git.get_default_branch("origin")?,
+    };
+    let read_only = params.find_frontier || params.dry_run;
+    if target_path.exists() && !read_only {
+        fs::remove_file(&target_path)?;
+    }
 
     if top_rev.contains('/') {
         git.update_upstream(top_rev.as_str())?;
     }
 
-    if git.has_file_changes()? {
+    if !read_only && git.has_file_changes()? {
         return Err("The repository has not committed changes, aborting.".into());
     }
 
-    let mut rev_list = git.rev_list("HEAD", top_rev.as_str(), true)?;
+    if params.check_submodules || params.strict {
+        git.check_submodules(params.strict)?;
+    }
 
-    if rev_list.is_empty() {
+    if git.merge_base("HEAD", top_rev.as_str())?.is_none() {
+        return Err(format!(
+            "HEAD and {} have no common history (unrelated histories), aborting.",
+            top_rev
+        )
+        .into());
+    }
+
+    // NOTE: the branch is often already up-to-date, no need to walk the history
+    let mut rev_list = if git.is_ancestor(top_rev.as_str(), "HEAD")? {
+        Vec::new()
+    } else {
+        git.rev_list("HEAD", top_rev.as_str(), true)?
+    };
+    let behind_before = rev_list.len();
+
+    let from = if let Some(onto) = params.onto.as_deref() {
+        let base = git.repo.revparse_single(onto)?.peel_to_commit()?.id();
+        let top = git.repo.revparse_single(&top_rev)?.peel_to_commit()?.id();
+        if base == top || !git.repo.graph_descendant_of(top, base)? {
+            return Err(format!("Revision {} is not an ancestor of {}.", onto, top_rev).into());
+        }
+
+        let pending = rev_list.drain(..).collect::<BTreeSet<_>>();
+        rev_list = git
+            .rev_list(onto, top_rev.as_str(), true)?
+            .into_iter()
+            .filter(|x| pending.contains(x))
+            .collect();
+
+        onto
+    } else {
+        "HEAD"
+    };
+
+    let upstream_first = git
+        .config
+        .get_bool("try-merge.first-parent-upstream")
+        .unwrap_or_default();
+
+    if rev_list.is_empty() && params.find_frontier {
+        say!("Your branch is already up-to-date.");
+        return Ok(SUCCESS);
+    } else if rev_list.is_empty() {
         let default_squash = git.config.get_bool("try-merge.squash").ok();
-        if params.squash || default_squash.unwrap_or_default() {
-            let commit = squash_all_merge_commits(&mut git, &top_rev)?;
-            if commit.is_some() {
-                println!("Your merge commits have been squashed.");
-                return Ok(());
+        if (params.squash || default_squash.unwrap_or_default()) && !params.dry_run {
+            let no_squash_set = git.glob_set("try-merge.no-squash-branches", &[], false)?;
+            let no_squash_branch = git
+                .branch_name
+                .as_ref()
+                .filter(|name| no_squash_set.is_match(name.as_str()));
+
+            if let Some(name) = no_squash_branch {
+                say!(
+                    "Squashing is disabled for the branch {} (try-merge.no-squash-branches).",
+                    name
+                );
+            } else if let Some(commit) = squash_all_merge_commits(
+                &mut git,
+                &top_rev,
+                params.trailer,
+                params.co_authors,
+                upstream_first,
+            )? {
+                if params.notes {
+                    add_merged_revisions_note(&git, &commit, upstream_first)?;
+                }
+                say!("Your merge commits have been squashed.");
+                write_github_output(&git, &top_rev, behind_before, None)?;
+                *outcome = json_outcome(&git, &top_rev, behind_before, None, None, &[])?;
+                if params.prune_merged {
+                    prune_merged_branches(&git, &params)?;
+                }
+                return Ok(SUCCESS);
             }
         }
-        println!("Your branch is already up-to-date.");
-        return Ok(());
+        let (ahead, _) = git.ahead_behind("HEAD", top_rev.as_str())?;
+        if ahead > 0 {
+            say!(
+                "Your branch is ahead of '{}' by {} commit(s), nothing to merge.",
+                top_rev,
+                ahead
+            );
+        } else {
+            say!("Your branch is already up-to-date.");
+        }
+        write_github_output(&git, &top_rev, behind_before, None)?;
+        *outcome = json_outcome(&git, &top_rev, behind_before, None, None, &[])?;
+        if params.prune_merged {
+            prune_merged_branches(&git, &params)?;
+        }
+        return Ok(SUCCESS);
     }
 
-    let mut builder = GlobSetBuilder::new();
-    for entry in git
-        .config
-        .multivar("try-merge.ignore-conflict", None)
-        .iter()
-        .flatten()
-        .filter_map(|x| x.ok())
-    {
-        builder.add(Glob::new(entry.value().expect("invalid UTF-8"))?);
+    let until_revision = if let Some(until) = params.until.as_deref() {
+        let revision = format!(
+            "{}",
+            git.repo.revparse_single(until)?.peel_to_commit()?.id()
+        );
+        match rev_list.iter().position(|x| *x == revision) {
+            Some(i) => rev_list.truncate(i + 1),
+            None => {
+                return Err(format!(
+                    "Revision {} is not between {} and {}.",
+                    until, from, top_rev
+                )
+                .into())
+            }
+        }
+
+        Some(revision)
+    } else {
+        None
+    };
+
+    // NOTE: the limit is handled like --until on the last revision allowed
+    let limited = matches!(params.limit, Some(limit) if rev_list.len() > limit);
+    let until_revision = match params.limit {
+        Some(0) => return Err("The limit must be at least 1 commit.".into()),
+        Some(limit) if limited => {
+            rev_list.truncate(limit);
+            rev_list.last().cloned()
+        }
+        _ => until_revision,
+    };
+
+    if !params.paths.is_empty() {
+        let paths = params.paths.iter().map(|x| x.as_str()).collect::<Vec<_>>();
+        let candidates = rev_list.iter().cloned().collect::<BTreeSet<_>>();
+        let last_revision = rev_list.pop();
+        rev_list = git
+            .rev_list_paths(
+                "HEAD",
+                last_revision.as_deref().expect("the list is not empty"),
+                true,
+                &paths,
+            )?
+            .into_iter()
+            .filter(|x| candidates.contains(x))
+            .collect();
+        // NOTE: the last revision is always a stop point to be able to be fully up-to-date
+        if rev_list.last() != last_revision.as_ref() {
+            rev_list.extend(last_revision);
+        }
+    }
+
+    let ignore_conflict_set = git.glob_set(
+        "try-merge.ignore-conflict",
+        &params.ignore_conflicts,
+        params.ignore_conflict_only,
+    )?;
+    let resolution = match params.conflict_resolution {
+        Some(x) => x,
+        None => git.conflict_resolution()?,
+    };
+    let regenerate_commands = git.regenerate_commands()?;
+    let auto_resolve_commits = config_auto_resolve_commits(&git)?;
+
+    if params.find_frontier {
+        return match find_frontier(
+            &git,
+            &rev_list,
+            &ignore_conflict_set,
+            resolution,
+            &regenerate_commands,
+        )? {
+            Some(revision) => {
+                let count = git.count_commits_between("HEAD", &revision)?;
+                say!("{} {}", revision, count);
+                Ok(SUCCESS)
+            }
+            None => {
+                eprintln!("No commit can be merged without conflict.");
+                Ok(if params.exit_code { CONFLICT } else { SUCCESS })
+            }
+        };
+    }
+
+    if params.dry_run {
+        let mut skipped = 0;
+        for revision in rev_list.iter().rev() {
+            if let Some(ignored) = git.check_no_conflict(
+                revision,
+                &ignore_conflict_set,
+                resolution,
+                &regenerate_commands,
+            )? {
+                say!(
+                    "The commits to {} ({} commit(s)) would be merged without conflict.",
+                    revision,
+                    git.count_commits_between("HEAD", revision)?
+                );
+                if !ignored.is_empty() {
+                    say!("The following files have conflicts that would be ignored:");
+                    for file_path in ignored {
+                        say!("{}", file_path);
+                    }
+                }
+                break;
+            }
+            skipped += 1;
+        }
+
+        if skipped > 0 {
+            say!(
+                "{} revision(s) would be skipped, the first merge conflict is on: {}",
+                skipped,
+                rev_list[rev_list.len() - skipped]
+            );
+        } else if params.prune_merged && until_revision.is_none() {
+            prune_merged_branches(&git, &params)?;
+        }
+        return Ok(SUCCESS);
+    }
+
+    let target = until_revision.as_deref().unwrap_or(top_rev.as_str());
+    if params.single_commit && git.list_conflicts("HEAD", target)?.is_empty() {
+        let revision = format!(
+            "{}",
+            git.repo.revparse_single(target)?.peel_to_commit()?.id()
+        );
+        // NOTE: the merge of the whole branch is not squashed with the next ones
+        let message = if until_revision.is_some() {
+            merge_message(
+                &format!("Merge commit {} (no conflict)", target),
+                &revision,
+                params.trailer,
+            )
+        } else {
+            append_trailers(
+                &format!("Merge branch {}\n\n", top_rev),
+                Some(format!("Try-Merge-Revision: {}", revision)).filter(|_| params.trailer),
+            )
+        };
+
+        if let Some(MergeReport {
+            ignored_conflicts, ..
+        }) = git.merge_no_conflict(
+            target,
+            message.as_str(),
+            &ignore_conflict_set,
+            resolution,
+            &regenerate_commands,
+            false,
+            params.preserve_author,
+            upstream_first,
+        )? {
+            if let Some(command) = params.verify_command.as_deref() {
+                if !verify_merge(&mut git, command, target, upstream_first)? {
+                    return Ok(FAILURE);
+                }
+            }
+            if params.notes {
+                add_merged_revisions_note(&git, &git.head_hash, upstream_first)?;
+            }
+            say!(
+                "All the commits to {} have been merged successfully without conflict",
+                target
+            );
+            write_github_output(&git, &top_rev, behind_before, None)?;
+            *outcome = json_outcome(
+                &git,
+                &top_rev,
+                behind_before,
+                Some(&revision),
+                None,
+                &ignored_conflicts,
+            )?;
+            if params.prune_merged && until_revision.is_none() {
+                prune_merged_branches(&git, &params)?;
+            }
+            return Ok(SUCCESS);
+        }
     }
-    let ignore_conflict_set = builder.build()?;
 
     let mut skipped = 0;
+    let mut merged_revision = None;
     let mut last_failing_revision: Option<String> = None;
-    let mut all_ignored_conflicts = HashSet::new();
+    let mut conflicting_revisions = Vec::new();
+    let mut all_ignored_conflicts = BTreeSet::new();
+    let total = rev_list.len();
+    let is_tty = std::io::stderr().is_terminal();
     while let Some(revision) = rev_list.pop() {
-        let message = format!("Merge commit {} (no conflict)\n\n", revision,);
+        let attempt = total - rev_list.len();
+        if !params.quiet {
+            print_progress(&git, &revision, attempt, total, is_tty, false)?;
+        }
 
-        if let Some((_, ignored_conflicts)) =
-            git.merge_no_conflict(revision.as_str(), message.as_str(), &ignore_conflict_set)?
-        {
-            println!(
+        let message = merge_message(
+            &format!("Merge commit {} (no conflict)", revision),
+            &revision,
+            params.trailer,
+        );
+
+        if let Some(MergeReport {
+            ignored_conflicts, ..
+        }) = git.merge_no_conflict(
+            revision.as_str(),
+            message.as_str(),
+            &ignore_conflict_set,
+            resolution,
+            &regenerate_commands,
+            false,
+            params.preserve_author,
+            upstream_first,
+        )? {
+            if !params.quiet {
+                print_progress(&git, &revision, attempt, total, is_tty, true)?;
+            }
+            if let Some(command) = params.verify_command.as_deref() {
+                if !verify_merge(&mut git, command, &revision, upstream_first)? {
+                    return Ok(FAILURE);
+                }
+            }
+            if params.notes {
+                add_merged_revisions_note(&git, &git.head_hash, upstream_first)?;
+            }
+            say!(
                 "All the commits to {} have been merged successfully without conflict",
                 revision
             );
             all_ignored_conflicts.extend(ignored_conflicts);
+            merged_revision = Some(revision);
 
             break;
         } else {
             skipped += 1;
             last_failing_revision = Some(revision.clone());
+            conflicting_revisions.push(revision);
         }
     }
+    if !params.quiet && is_tty {
+        eprint!("\r\x1b[K");
+    }
 
     if !all_ignored_conflicts.is_empty() {
-        println!("The following files had conflicts but have been ignored:");
-        for file_path in all_ignored_conflicts {
-            println!("{}", file_path);
+        say!("The following files had conflicts but have been ignored:");
+        let shown = if params.show_all_ignored {
+            all_ignored_conflicts.len()
+        } else {
+            MAX_IGNORED_CONFLICTS_SHOWN
+        };
+        for file_path in all_ignored_conflicts.iter().take(shown) {
+            say!("{}", file_path);
+        }
+        if all_ignored_conflicts.len() > shown {
+            say!("...and {} more", all_ignored_conflicts.len() - shown);
         }
     }
 
+    write_github_output(
+        &git,
+        &top_rev,
+        behind_before,
+        last_failing_revision.as_deref(),
+    )?;
+    *outcome = json_outcome(
+        &git,
+        &top_rev,
+        behind_before,
+        merged_revision.as_deref(),
+        last_failing_revision.as_deref(),
+        &all_ignored_conflicts.iter().cloned().collect::<Vec<_>>(),
+    )?;
+
+    if params.keep_going && !conflicting_revisions.is_empty() {
+        // NOTE: all the revisions after the first conflict conflict too, only the ones whose
+        //       first parent merges without conflict (or with fewer conflicting files)
+        //       introduce a conflict
+        let mut introducing = Vec::new();
+        for revision in conflicting_revisions.iter().rev() {
+            let is_mergeable = |revision: &str| -> Result<bool, git2::Error> {
+                Ok(git
+                    .check_no_conflict(
+                        revision,
+                        &ignore_conflict_set,
+                        resolution,
+                        &regenerate_commands,
+                    )?
+                    .is_some())
+            };
+            if is_mergeable(revision)? {
+                continue;
+            }
+
+            let parent = format!("{}^", revision);
+            let parent_conflicts = git.list_conflicts("HEAD", &parent)?;
+            if is_mergeable(&parent)?
+                || git
+                    .list_conflicts("HEAD", revision)?
+                    .iter()
+                    .any(|x| !parent_conflicts.contains(x))
+            {
+                introducing.push(revision);
+            }
+        }
+
+        say!("The following commits introduce conflicts and have been skipped:");
+        for revision in introducing {
+            say!("{} ({})", revision, git.describe(revision)?);
+        }
+        return Ok(if params.exit_code { CONFLICT } else { SUCCESS });
+    }
+
     if params.no_merge {
-        return Ok(());
+        if params.exit_code && last_failing_revision.is_some() {
+            return Ok(CONFLICT);
+        }
+        return Ok(SUCCESS);
     } else if let Some(revision) = last_failing_revision {
-        println!(
+        let behind = if params.paths.is_empty() && until_revision.is_none() && params.onto.is_none()
+        {
+            skipped
+        } else {
+            git.count_commits_between("HEAD", top_rev.as_str())?
+        };
+        say!(
             "Your current branch is still behind '{}' by {} commit(s).",
-            top_rev, skipped
+            top_rev,
+            behind
         );
-        println!("First merge conflict detected on: {}", revision);
+        say!(
+            "First merge conflict detected on: {} ({})",
+            revision,
+            git.describe(&revision)?
+        );
+
+        if is_auto_resolve_commit(&git, &auto_resolve_commits, &revision)? {
+            let message = merge_message(
+                &format!("Merge commit {} (conflicts auto-resolved)", revision),
+                &revision,
+                params.trailer,
+            );
+            let take_all = GlobSetBuilder::new().add(Glob::new("*")?).build()?;
 
-        let message = format!("Merge commit {} (conflicts)\n\n", revision,);
+            if let Some(MergeReport {
+                ignored_conflicts: resolved,
+                ..
+            }) = git.merge_no_conflict(
+                revision.as_str(),
+                message.as_str(),
+                &take_all,
+                ConflictResolution::Theirs,
+                &regenerate_commands,
+                false,
+                params.preserve_author,
+                upstream_first,
+            )? {
+                say!(
+                    "The conflicts of {} have been resolved with the upstream version \
+                    (try-merge.auto-resolve-commits):",
+                    revision
+                );
+                for path in resolved {
+                    say!("{}", path);
+                }
 
-        return Err(Command::new("git")
-            .args(&[
+                // NOTE: the next commits are merged from scratch, like on a new run
+                let mut params = params;
+                params.revision = Some(top_rev);
+                params.resume = false;
+                return update_branch(git, params, outcome);
+            }
+        }
+
+        let message = merge_message(
+            &format!("Merge commit {} (conflicts)", revision),
+            &revision,
+            params.trailer,
+        );
+        fs::write(&target_path, &top_rev)?;
+
+        if params.internal_merge {
+            let conflicts = git.merge_in_worktree(&revision, &message)?;
+            say!("Automatic merge failed; fix the conflicts and then commit the result:");
+            for path in conflicts {
+                say!("    {}", path);
+            }
+
+            return Ok(if params.exit_code { CONFLICT } else { FAILURE });
+        }
+
+        let mut command = Command::new("git");
+        command
+            .args([
                 "merge",
                 "--no-ff",
                 revision.as_str(),
                 "-m",
                 message.as_str(),
             ])
-            .args(params.merge_args)
-            .exec()
-            .into());
+            .args(params.quiet.then_some("--quiet"))
+            .args(params.ignore_whitespace.then_some("-Xignore-all-space"))
+            .args(params.merge_args);
+        if output::is_json() {
+            command.stdout(std::io::stderr());
+        }
+
+        if params.exit_code {
+            let status = command.status()?;
+            return Ok(if status.success() { SUCCESS } else { CONFLICT });
+        }
+        // NOTE: the stash and the JSON output are handled once `git merge` has finished
+        if params.autostash || output::is_json() {
+            let status = command.status()?;
+            return Ok(status.code().unwrap_or(FAILURE));
+        }
+
+        return Err(command.exec().into());
+    } else if let Some(revision) = until_revision {
+        say!("Nothing more to merge until {}.", revision);
+        if limited {
+            say!(
+                "The limit of {} commit(s) has been reached, {} commit(s) remaining.",
+                params.limit.expect("the merge is limited"),
+                git.count_commits_between("HEAD", top_rev.as_str())?
+            );
+        }
+    } else {
+        say!("Nothing more to merge. Your branch is up-to-date.");
+        if params.prune_merged {
+            prune_merged_branches(&git, &params)?;
+        }
+    }
+
+    Ok(SUCCESS)
+}
+
+/// Append the outcome of the merge to the file of `GITHUB_OUTPUT` (GitHub Actions step outputs)
+/// if it is set.
+fn write_github_output(
+    git: &Git,
+    top_rev: &str,
+    behind_before: usize,
+    first_conflict: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let path = match env::var_os("GITHUB_OUTPUT") {
+        Some(x) => x,
+        None => return Ok(()),
+    };
+    let behind = git.count_commits_between("HEAD", top_rev)?;
+
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    writeln!(
+        file,
+        "merged_count={}",
+        behind_before.saturating_sub(behind)
+    )?;
+    writeln!(file, "skipped_count={}", behind)?;
+    writeln!(
+        file,
+        "first_conflict={}",
+        first_conflict.unwrap_or_default()
+    )?;
+    writeln!(file, "head_hash={}", git.head_hash)?;
+
+    Ok(())
+}
+
+/// Outcome of the merge for the JSON output, `None` if it is not enabled.
+fn json_outcome(
+    git: &Git,
+    top_rev: &str,
+    behind_before: usize,
+    merged_revision: Option<&str>,
+    first_conflict: Option<&str>,
+    ignored_conflicts: &[String],
+) -> Result<Option<Json>, Box<dyn std::error::Error>> {
+    if !output::is_json() {
+        return Ok(None);
+    }
+    let behind = git.count_commits_between("HEAD", top_rev)?;
+
+    Ok(Some(Json::Object(vec![
+        ("merged_revision", merged_revision.into()),
+        ("merged_count", behind_before.saturating_sub(behind).into()),
+        ("skipped_count", behind.into()),
+        ("first_conflict", first_conflict.into()),
+        ("ignored_conflicts", ignored_conflicts.to_vec().into()),
+        ("head_hash", git.head_hash.as_str().into()),
+    ])))
+}
+
+/// Returns `false` if the command failed, the merge commit is then undone.
+fn verify_merge(
+    git: &mut Git,
+    command: &str,
+    revision: &str,
+    upstream_first: bool,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(command);
+    if output::is_json() {
+        shell.stdout(std::io::stderr());
+    }
+    let status = shell.status()?;
+    if status.success() {
+        return Ok(true);
+    }
+
+    let commit = git.repo.revparse_single("HEAD")?.peel_to_commit()?;
+    let parent = commit.parent(our_parent_index(upstream_first))?;
+    git.repo
+        .reset(parent.as_object(), git2::ResetType::Soft, None)?;
+    git.head_hash = format!("{}", parent.id());
+
+    say!(
+        "Verification failed after merging {}: the merge commit has been undone.",
+        revision
+    );
+
+    Ok(false)
+}
+
+fn prune_merged_branches(git: &Git, params: &TryMerge) -> Result<(), Box<dyn std::error::Error>> {
+    let default_branch = git.get_default_branch("origin")?;
+    // NOTE: the local branch of the default branch is never deleted
+    let local_default_branch = default_branch.split_once('/').map(|x| x.1);
+
+    for name in git.merged_branches(&default_branch)? {
+        if Some(name.as_str()) == git.branch_name.as_deref()
+            || Some(name.as_str()) == local_default_branch
+        {
+            continue;
+        }
+
+        if params.dry_run {
+            say!("Would delete merged branch: {}", name);
+        } else if params.prune_remote {
+            let mut command = Command::new("git");
+            command.args(["delete", name.as_str()]);
+            if output::is_json() {
+                command.stdout(std::io::stderr());
+            }
+            let status = command.status()?;
+            if !status.success() {
+                return Err(format!("Could not delete branch {}.", name).into());
+            }
+        } else {
+            git.repo
+                .find_branch(&name, git2::BranchType::Local)?
+                .delete()?;
+            say!("Merged branch deleted: {}", name);
+        }
+    }
+
+    Ok(())
+}
+
+enum AutoResolve {
+    Hash(String),
+    Message(GlobMatcher),
+}
+
+/// The values of `try-merge.auto-resolve-commits` are hash prefixes (7 hexadecimal digits or
+/// more) or glob patterns matched against the first line of the commit message.
+fn config_auto_resolve_commits(git: &Git) -> Result<Vec<AutoResolve>, Box<dyn std::error::Error>> {
+    const NAME: &str = "try-merge.auto-resolve-commits";
+
+    let mut patterns = Vec::new();
+    for entry in git
+        .config
+        .multivar(NAME, None)
+        .iter()
+        .flatten()
+        .filter_map(|x| x.ok())
+    {
+        let pattern = entry.value().expect("invalid UTF-8").trim();
+        // NOTE: the conflicts are resolved blindly, a pattern matching anything is refused
+        if pattern.chars().all(|x| matches!(x, '*' | '?' | ' ')) {
+            return Err(format!("The pattern `{}` of {} is not explicit.", pattern, NAME).into());
+        }
+
+        if pattern.len() >= 7 && pattern.chars().all(|x| x.is_ascii_hexdigit()) {
+            patterns.push(AutoResolve::Hash(pattern.to_lowercase()));
+        } else {
+            patterns.push(AutoResolve::Message(Glob::new(pattern)?.compile_matcher()));
+        }
+    }
+
+    Ok(patterns)
+}
+
+fn is_auto_resolve_commit(
+    git: &Git,
+    patterns: &[AutoResolve],
+    revision: &str,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let commit = git.repo.revparse_single(revision)?.peel_to_commit()?;
+    let hash = format!("{}", commit.id());
+    let summary = commit.summary().unwrap_or_default();
+
+    Ok(patterns.iter().any(|pattern| match pattern {
+        AutoResolve::Hash(prefix) => hash.starts_with(prefix),
+        AutoResolve::Message(glob) => glob.is_match(summary),
+    }))
+}
+
+/// Index of the parent of the merge commits that is on the current branch.
+fn our_parent_index(upstream_first: bool) -> usize {
+    if upstream_first {
+        1
     } else {
-        println!("Nothing more to merge. Your branch is up-to-date.");
+        0
+    }
+}
+
+fn add_merged_revisions_note(
+    git: &Git,
+    commit: &str,
+    upstream_first: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let commit = git.repo.revparse_single(commit)?.peel_to_commit()?;
+    let ours = our_parent_index(upstream_first);
+    let revisions = git.rev_list(
+        &format!("{}", commit.parent_id(ours)?),
+        &format!("{}", commit.parent_id(1 - ours)?),
+        true,
+    )?;
+
+    let mut message = String::from("Merged revisions:\n");
+    for revision in revisions {
+        message.push_str(&revision);
+        message.push('\n');
+    }
+    git.append_note(&format!("{}", commit.id()), &message)?;
+
+    Ok(())
+}
+
+/// Furthest revision of `rev_list` (oldest first) that merges into HEAD without conflict.
+fn find_frontier(
+    git: &Git,
+    rev_list: &[String],
+    ignore_conflict_set: &GlobSet,
+    resolution: ConflictResolution,
+    regenerate_commands: &[(GlobMatcher, String)],
+) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    let is_mergeable = |revision: &str| -> Result<bool, git2::Error> {
+        Ok(git
+            .check_no_conflict(
+                revision,
+                ignore_conflict_set,
+                resolution,
+                regenerate_commands,
+            )?
+            .is_some())
+    };
+
+    // NOTE: invariant: the revisions before `low` are mergeable, the ones from `high` are not
+    let (mut low, mut high) = (0, rev_list.len());
+    while low < high {
+        let middle = low + (high - low) / 2;
+        if is_mergeable(&rev_list[middle])? {
+            low = middle + 1;
+        } else {
+            high = middle;
+        }
+    }
+
+    Ok(low.checked_sub(1).map(|i| rev_list[i].clone()))
+}
+
+/// The revisions are tried from the newest one: `attempt` counts the attempts, it is not the
+/// position of the revision. Called again with `merged` once the revision has been merged: the
+/// line is then cleared on a terminal, the merged revision is reported right after.
+fn print_progress(
+    git: &Git,
+    revision: &str,
+    attempt: usize,
+    total: usize,
+    is_tty: bool,
+    merged: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // NOTE: no redraw possible without a terminal, only a line from time to time is printed and
+    //       the merged revision is always printed (once)
+    let periodic = attempt == 1 || attempt == total || attempt.is_multiple_of(10);
+    let shown = if is_tty {
+        if merged {
+            eprint!("\r\x1b[K");
+        }
+        !merged
+    } else if merged {
+        !periodic
+    } else {
+        periodic
+    };
+    if !shown {
+        return Ok(());
+    }
+
+    let commit = git.repo.revparse_single(revision)?.peel_to_commit()?;
+    let line = format!(
+        "Trying {}/{}: {} {}",
+        attempt,
+        total,
+        &revision[..7],
+        commit.summary().unwrap_or_default()
+    );
+
+    if is_tty {
+        eprint!("\r\x1b[K{}", line);
+    } else {
+        eprintln!("{}", line);
     }
 
     Ok(())
@@ -166,25 +1241,83 @@ fn update_branch(mut git: Git, params: TryMerge) -> Result<(), Box<dyn std::erro
 fn squash_all_merge_commits(
     git: &mut Git,
     top_rev: &str,
+    trailer: bool,
+    co_authors: bool,
+    upstream_first: bool,
 ) -> Result<Option<String>, Box<dyn std::error::Error>> {
-    let merge_commits = git.ancestors("HEAD")?.take_while(|commit| {
-        commit
-            .message()
-            .map(|msg| msg.starts_with("Merge commit"))
-            .unwrap_or_default()
-    });
-    if let Some(ancestor) = merge_commits
-        // NOTE: we need to have more than 1 commit to make a squash
-        .skip(1)
-        .last()
-        .map(|x| format!("{}", x.parent(0).unwrap().id()))
+    // NOTE: the merge commits are followed through the parent on the current branch
+    let ours = our_parent_index(upstream_first);
+    let mut merge_commits = Vec::new();
     {
-        Ok(Some(git.squash(
-            &ancestor,
-            top_rev,
-            &format!("Merge branch {}", top_rev),
-        )?))
+        let mut commit = git.repo.revparse_single("HEAD")?.peel_to_commit()?;
+        while commit.parent_count() == 2 && is_try_merge_commit(&commit) {
+            merge_commits.push((
+                format!("{}", commit.parent_id(ours)?),
+                format!("{}", commit.parent_id(1 - ours)?),
+            ));
+            commit = commit.parent(ours)?;
+        }
+    }
+
+    // NOTE: we need to have more than 1 commit to make a squash
+    if merge_commits.len() > 1 {
+        let ancestor = &merge_commits.last().unwrap().0;
+        let mut trailers = Vec::new();
+        if trailer {
+            for (_, revision) in merge_commits.iter().rev() {
+                trailers.push(format!("Try-Merge-Revision: {}", revision));
+            }
+        }
+        if co_authors {
+            for author in merged_authors(git, ancestor, top_rev)? {
+                trailers.push(format!("Co-authored-by: {}", author));
+            }
+        }
+        let message = append_trailers(&format!("Merge branch {}", top_rev), trailers);
+
+        let (parent_0, parent_1) = if upstream_first {
+            (top_rev, ancestor.as_str())
+        } else {
+            (ancestor.as_str(), top_rev)
+        };
+
+        Ok(Some(git.squash(parent_0, parent_1, &message)?))
     } else {
         Ok(None)
     }
 }
+
+/// Distinct authors of the merged commits, in order of appearance, except the current user.
+fn merged_authors(
+    git: &Git,
+    from: &str,
+    to: &str,
+) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let own_email = git.repo.signature()?.email().map(|x| x.to_lowercase());
+
+    let mut seen = BTreeSet::new();
+    let mut authors = Vec::new();
+    for revision in git.rev_list(from, to, true)? {
+        let commit = git.repo.revparse_single(&revision)?.peel_to_commit()?;
+        let author = commit.author();
+        let email = author.email().unwrap_or_default().to_lowercase();
+
+        if Some(&email) == own_email.as_ref() || !seen.insert(email) {
+            continue;
+        }
+        authors.push(format!(
+            "{} <{}>",
+            author.name().unwrap_or_default(),
+            author.email().unwrap_or_default()
+        ));
+    }
+
+    Ok(authors)
+}
+
+fn is_try_merge_commit(commit: &git2::Commit) -> bool {
+    commit
+        .message()
+        .map(|msg| msg.lines().any(|x| x.trim_end() == MERGE_TRAILER))
+        .unwrap_or_default()
+}